@@ -0,0 +1,732 @@
+use crate::cpu::{MEMORY_SIZE, PROGRAM_START};
+use crate::error::AsmError;
+use std::collections::HashMap;
+
+// Guards against macros that expand themselves forever.
+const MAX_EXPANSIONS: usize = 10_000;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A label used before its definition, patched into the ROM at the end.
+struct Fixup {
+    offset: usize,
+    label: String,
+    long: bool, // 16-bit operand word of F000 NNNN instead of the NNN of an opcode
+    line: usize,
+}
+
+// A `loop` and the `while` jumps out of it waiting for the matching `again`.
+struct Loop {
+    start: u16,
+    exits: Vec<usize>,
+}
+
+// Opcodes skipping the next instruction when a condition is false or true.
+struct Condition {
+    skip_if_false: u16,
+    skip_if_true: u16,
+}
+
+struct Assembler {
+    tokens: Vec<Token>, // Remaining tokens in reverse order
+    rom: Vec<u8>,
+    line: usize,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<Loop>,
+    blocks: Vec<usize>, // Offsets of the jumps of open `begin` and `else` blocks
+    expansions: usize,
+    main_jump: bool, // The ROM starts with a placeholder jump to `main`
+}
+
+// Assemble Octo source into a ROM to be loaded at PROGRAM_START.
+//
+// Supported are all instructions in Octo syntax, labels (`: name`), `:alias`,
+// `:const`, `:macro`, `:call`, `loop`/`while`/`again`, `if ... then` and
+// `if ... begin ... else ... end`, and bare numbers as data bytes. A bare
+// label name calls the subroutine. Like Octo, the program starts with a jump
+// to `main` unless `main` is defined before anything is emitted; without
+// `main` the program starts with the first statement.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = tokenize(source);
+    let has_main = tokens
+        .windows(2)
+        .any(|pair| pair[0].text == ":" && pair[1].text == "main");
+    tokens.reverse();
+
+    let mut assembler = Assembler {
+        tokens,
+        rom: Vec::new(),
+        line: 1,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+        main_jump: has_main,
+    };
+    if has_main {
+        assembler.fixup(0x1000, "main", false);
+    }
+    while let Some(token) = assembler.tokens.pop() {
+        assembler.line = token.line;
+        assembler.statement(&token.text)?;
+    }
+    assembler.finish()
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(number, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |text| Token {
+                text: text.to_string(),
+                line: number + 1,
+            })
+        })
+        .collect()
+}
+
+fn parse_literal(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl Assembler {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError {
+            line: self.line,
+            message,
+        })
+    }
+
+    fn next(&mut self, what: &str) -> Result<String, AsmError> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error(format!("expected {} at end of file", what)),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next(expected)?;
+        if token != expected {
+            return self.error(format!("expected {}, found {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn here(&self) -> u16 {
+        (PROGRAM_START + self.rom.len()) as u16
+    }
+
+    // Remove the placeholder jump to `main` when `main` directly follows it,
+    // moving what was defined after it back to the start.
+    fn drop_main_jump(&mut self) {
+        self.rom.clear();
+        self.fixups.remove(0);
+        let start = PROGRAM_START as u16;
+        for address in self.labels.values_mut() {
+            *address = start;
+        }
+        for repeat in &mut self.loops {
+            repeat.start = start;
+        }
+        self.main_jump = false;
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.rom.push((opcode >> 8) as u8);
+        self.rom.push(opcode as u8);
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), AsmError> {
+        match token {
+            ":" => {
+                let name = self.identifier()?;
+                if name == "main" && self.main_jump && self.rom.len() == 2 {
+                    self.drop_main_jump();
+                }
+                if self.labels.insert(name.clone(), self.here()).is_some() {
+                    return self.error(format!("label {} is defined twice", name));
+                }
+            }
+            ":alias" => {
+                let name = self.identifier()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.identifier()?;
+                let value = self.next("value")?;
+                let value = self.value(&value)?;
+                self.consts.insert(name, value);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.next("address")?;
+                self.address_instruction(0x2000, &target)?;
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "jump" => {
+                let target = self.next("address")?;
+                self.address_instruction(0x1000, &target)?;
+            }
+            "jump0" => {
+                let target = self.next("address")?;
+                self.address_instruction(0xB000, &target)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n);
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "audio" => self.emit(0xF002),
+            "bcd" => self.register_instruction(0xF033)?,
+            "save" => self.load_store(0xF055, 0x5002)?,
+            "load" => self.load_store(0xF065, 0x5003)?,
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "delay" => {
+                self.expect(":=")?;
+                self.register_instruction(0xF015)?;
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                self.register_instruction(0xF018)?;
+            }
+            "pitch" => {
+                self.expect(":=")?;
+                self.register_instruction(0xF03A)?;
+            }
+            "i" => self.index()?,
+            "loop" => self.loops.push(Loop {
+                start: self.here(),
+                exits: Vec::new(),
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return self.error("while outside of a loop".to_string());
+                }
+                let condition = self.condition()?;
+                self.emit(condition.skip_if_true);
+                let exit = self.rom.len();
+                self.emit(0x1000);
+                self.loops.last_mut().unwrap().exits.push(exit);
+            }
+            "again" => {
+                let repeat = match self.loops.pop() {
+                    Some(repeat) => repeat,
+                    None => return self.error("again without loop".to_string()),
+                };
+                let opcode = self.jump(repeat.start)?;
+                self.emit(opcode);
+                for exit in repeat.exits {
+                    self.patch_jump(exit)?;
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next("then or begin")?.as_str() {
+                    "then" => self.emit(condition.skip_if_false),
+                    "begin" => {
+                        self.emit(condition.skip_if_true);
+                        self.blocks.push(self.rom.len());
+                        self.emit(0x1000);
+                    }
+                    other => return self.error(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let skip = match self.blocks.pop() {
+                    Some(skip) => skip,
+                    None => return self.error("else without begin".to_string()),
+                };
+                self.blocks.push(self.rom.len());
+                self.emit(0x1000);
+                self.patch_jump(skip)?;
+            }
+            "end" => match self.blocks.pop() {
+                Some(skip) => self.patch_jump(skip)?,
+                None => return self.error("end without begin".to_string()),
+            },
+            _ => self.other(token)?,
+        }
+        Ok(())
+    }
+
+    // Register operations, macro invocations, data bytes and calls.
+    fn other(&mut self, token: &str) -> Result<(), AsmError> {
+        if let Some(x) = self.register_name(token) {
+            return self.register_operation(x);
+        }
+        if self.macros.contains_key(token) {
+            return self.expand_macro(token);
+        }
+        if let Some(value) = self.constant(token) {
+            let byte = self.check_byte(value)?;
+            self.rom.push(byte);
+            return Ok(());
+        }
+        if parse_literal(token).is_none()
+            && token.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        {
+            return self.address_instruction(0x2000, token);
+        }
+        self.error(format!("unknown statement {}", token))
+    }
+
+    fn register_operation(&mut self, x: u8) -> Result<(), AsmError> {
+        let x = (x as u16) << 8;
+        let operator = self.next("operator")?;
+        let operand = self.next("operand")?;
+        let y = self.register_name(&operand).map(|y| (y as u16) << 4);
+        let opcode = match (operator.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x | y,
+            (":=", None) => match operand.as_str() {
+                "random" => {
+                    let mask = self.next("mask")?;
+                    0xC000 | x | self.byte(&mask)? as u16
+                }
+                "delay" => 0xF007 | x,
+                "key" => 0xF00A | x,
+                _ => 0x6000 | x | self.byte(&operand)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | x | y,
+            ("+=", None) => 0x7000 | x | self.byte(&operand)? as u16,
+            ("-=", Some(y)) => 0x8005 | x | y,
+            ("-=", None) => 0x7000 | x | (self.byte(&operand)?.wrapping_neg()) as u16,
+            ("|=", Some(y)) => 0x8001 | x | y,
+            ("&=", Some(y)) => 0x8002 | x | y,
+            ("^=", Some(y)) => 0x8003 | x | y,
+            (">>=", Some(y)) => 0x8006 | x | y,
+            ("=-", Some(y)) => 0x8007 | x | y,
+            ("<<=", Some(y)) => 0x800E | x | y,
+            _ => return self.error(format!("invalid operation {} {}", operator, operand)),
+        };
+        self.emit(opcode);
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), AsmError> {
+        match self.next("operator")?.as_str() {
+            ":=" => {}
+            "+=" => return self.register_instruction(0xF01E),
+            other => return self.error(format!("invalid operation on i: {}", other)),
+        }
+        let operand = self.next("address")?;
+        match operand.as_str() {
+            "hex" => self.register_instruction(0xF029),
+            "bighex" => self.register_instruction(0xF030),
+            "long" => {
+                let target = self.next("address")?;
+                self.emit(0xF000);
+                match self.constant(&target) {
+                    Some(value) if (0..MEMORY_SIZE as i32).contains(&value) => {
+                        self.emit(value as u16)
+                    }
+                    Some(value) => return self.error(format!("address {} out of range", value)),
+                    None => self.label_reference(0, &target, true)?,
+                }
+                Ok(())
+            }
+            _ => self.address_instruction(0xA000, &operand),
+        }
+    }
+
+    // FX55/FX65 for `save vx`, 5XY2/5XY3 for `save vx - vy`.
+    fn load_store(&mut self, single: u16, range: u16) -> Result<(), AsmError> {
+        let x = self.register()? as u16;
+        if self.peek() == Some("-") {
+            self.next("-")?;
+            let y = self.register()? as u16;
+            self.emit(range | x << 8 | y << 4);
+        } else {
+            self.emit(single | x << 8);
+        }
+        Ok(())
+    }
+
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let x = self.register()?;
+        self.emit(opcode | (x as u16) << 8);
+        Ok(())
+    }
+
+    // An instruction with a 12-bit address, given as a number or a label.
+    fn address_instruction(&mut self, opcode: u16, target: &str) -> Result<(), AsmError> {
+        match self.constant(target) {
+            Some(value) if (0..=0xFFF).contains(&value) => self.emit(opcode | value as u16),
+            Some(value) => return self.error(format!("address {} out of range", value)),
+            None => self.label_reference(opcode, target, false)?,
+        }
+        Ok(())
+    }
+
+    // Emit `opcode` or, for a long reference, the operand word with the
+    // address of `label`, resolved now or once the label is defined.
+    fn label_reference(&mut self, opcode: u16, label: &str, long: bool) -> Result<(), AsmError> {
+        if label.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            return self.error(format!("invalid address {}", label));
+        }
+        match self.labels.get(label) {
+            Some(&address) if !long && address > 0xFFF => {
+                return self.error(format!("label {} is beyond 0xFFF, use i := long", label))
+            }
+            Some(&address) => self.emit(opcode | address),
+            None => self.fixup(opcode, label, long),
+        }
+        Ok(())
+    }
+
+    // Emit `opcode` and patch the address of `label` into it at the end.
+    fn fixup(&mut self, opcode: u16, label: &str, long: bool) {
+        self.fixups.push(Fixup {
+            offset: self.rom.len(),
+            label: label.to_string(),
+            long,
+            line: self.line,
+        });
+        self.emit(opcode);
+    }
+
+    fn jump(&self, address: u16) -> Result<u16, AsmError> {
+        if address > 0xFFF {
+            return self.error(format!("cannot jump to {:#06x} beyond 0xFFF", address));
+        }
+        Ok(0x1000 | address)
+    }
+
+    // Point the placeholder jump at `offset` to the current address.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), AsmError> {
+        let opcode = self.jump(self.here())?;
+        self.rom[offset] = (opcode >> 8) as u8;
+        self.rom[offset + 1] = opcode as u8;
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = (self.register()? as u16) << 8;
+        let operator = self.next("comparison")?;
+        let (skip_if_false, skip_if_true) = match operator.as_str() {
+            "key" => (0xE0A1 | x, 0xE09E | x),
+            "-key" => (0xE09E | x, 0xE0A1 | x),
+            "==" | "!=" => {
+                let operand = self.next("operand")?;
+                let (equal, not_equal) = match self.register_name(&operand) {
+                    Some(y) => (0x5000 | x | (y as u16) << 4, 0x9000 | x | (y as u16) << 4),
+                    None => {
+                        let nn = self.byte(&operand)? as u16;
+                        (0x3000 | x | nn, 0x4000 | x | nn)
+                    }
+                };
+                // The skip instructions skip when the comparison holds
+                if operator == "==" {
+                    (not_equal, equal)
+                } else {
+                    (equal, not_equal)
+                }
+            }
+            other => return self.error(format!("unknown comparison {}", other)),
+        };
+        Ok(Condition {
+            skip_if_false,
+            skip_if_true,
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.identifier()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next("{")?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.pop();
+            let token = match token {
+                Some(token) => token,
+                None => return self.error(format!("macro {} is missing a closing }}", name)),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // Replace a macro invocation by its body with the arguments substituted.
+    fn expand_macro(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.error(format!("too many expansions of macro {}", name));
+        }
+        let param_count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for index in 0..param_count {
+            let arg = self.next("macro argument")?;
+            args.insert(self.macros[name].params[index].clone(), arg);
+        }
+        let line = self.line;
+        let expansion: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .rev()
+            .map(|token| Token {
+                text: args.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+            })
+            .collect();
+        self.tokens.extend(expansion);
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<String, AsmError> {
+        let name = self.next("name")?;
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return self.error(format!("invalid name {}", name));
+        }
+        Ok(name)
+    }
+
+    fn register_name(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next("register")?;
+        match self.register_name(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register, found {}", token)),
+        }
+    }
+
+    // The value of a number literal or a constant.
+    fn constant(&self, text: &str) -> Option<i32> {
+        parse_literal(text).or_else(|| self.consts.get(text).copied())
+    }
+
+    fn value(&self, text: &str) -> Result<i32, AsmError> {
+        match self.constant(text) {
+            Some(value) => Ok(value),
+            None => self.error(format!("expected a number, found {}", text)),
+        }
+    }
+
+    fn check_byte(&self, value: i32) -> Result<u8, AsmError> {
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, AsmError> {
+        self.check_byte(self.value(text)?)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        let token = self.next("number")?;
+        let value = self.value(&token)?;
+        if !(0..=15).contains(&value) {
+            return self.error(format!("{} does not fit in a nibble", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if !self.loops.is_empty() {
+            return self.error("loop without again".to_string());
+        }
+        if !self.blocks.is_empty() {
+            return self.error("begin without end".to_string());
+        }
+        for fixup in &self.fixups {
+            let address = match self.labels.get(&fixup.label) {
+                Some(&address) => address,
+                None => {
+                    return Err(AsmError {
+                        line: fixup.line,
+                        message: format!("undefined label {}", fixup.label),
+                    })
+                }
+            };
+            let mut word = (self.rom[fixup.offset] as u16) << 8 | self.rom[fixup.offset + 1] as u16;
+            if fixup.long {
+                word = address;
+            } else if address > 0xFFF {
+                return Err(AsmError {
+                    line: fixup.line,
+                    message: format!("label {} is beyond 0xFFF, use i := long", fixup.label),
+                });
+            } else {
+                word |= address;
+            }
+            self.rom[fixup.offset] = (word >> 8) as u8;
+            self.rom[fixup.offset + 1] = word as u8;
+        }
+        if self.rom.len() > MEMORY_SIZE - PROGRAM_START {
+            return self.error(format!("program is {} bytes, too large", self.rom.len()));
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::write_disassembly;
+
+    fn error_line(source: &str) -> usize {
+        assemble(source).unwrap_err().line
+    }
+
+    #[test]
+    fn main_defined_first_needs_no_jump() {
+        assert_eq!(assemble(": main clear"), Ok(vec![0x00, 0xE0]));
+        assert_eq!(
+            assemble(":alias x v3 :const n 5 : main x := n"),
+            Ok(vec![0x63, 0x05])
+        );
+        assert_eq!(assemble(": start : main jump start"), Ok(vec![0x12, 0x00]));
+    }
+
+    #[test]
+    fn main_after_code_is_jumped_to() {
+        assert_eq!(
+            assemble(": sub return : main sub"),
+            Ok(vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02])
+        );
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        assert_eq!(
+            assemble("i := data jump end : data 0xFF : end i := long data"),
+            Ok(vec![0xA2, 0x04, 0x12, 0x05, 0xFF, 0xF0, 0x00, 0x02, 0x04])
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            assemble("loop while v0 != 3 v0 += 1 again"),
+            Ok(vec![0x40, 0x03, 0x12, 0x08, 0x70, 0x01, 0x12, 0x00])
+        );
+        assert_eq!(
+            assemble("if v1 == v2 begin clear else return end"),
+            Ok(vec![
+                0x51, 0x20, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x0A, 0x00, 0xEE
+            ])
+        );
+    }
+
+    #[test]
+    fn expands_macros() {
+        assert_eq!(
+            assemble(":macro set r n { r := n } set v1 2 set v2 3"),
+            Ok(vec![0x61, 0x02, 0x62, 0x03])
+        );
+        assert_eq!(error_line(":macro m { m }\nm"), 2);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(error_line("clear\njump nowhere"), 2);
+        assert_eq!(error_line("clear\n\nv0 := 256"), 3);
+        assert_eq!(error_line("jump 0x1000"), 1);
+        assert_eq!(error_line("clear\nloop"), 2);
+        assert_eq!(error_line("sprite v0 v1 16"), 1);
+    }
+
+    #[test]
+    fn disassembly_assembles_back_to_the_rom() {
+        let rom = assemble(
+            ": main
+                i := sprite
+                loop
+                    v0 := random 0x3F
+                    v1 := key
+                    sprite v0 v1 5
+                    if v1 == 5 then draw
+                again
+             : draw
+                i := hex v1
+                save v2
+                return
+             : sprite 0xF0 0x90 0xF0 0x90 0xF0 1",
+        )
+        .unwrap();
+        let mut source = Vec::new();
+        write_disassembly(&mut source, &rom).unwrap();
+        assert_eq!(assemble(&String::from_utf8(source).unwrap()), Ok(rom));
+    }
+}
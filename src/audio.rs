@@ -0,0 +1,137 @@
+use crate::cpu::{AudioPattern, TIMER_FREQUENCY};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform: {}", name)),
+        }
+    }
+}
+
+// Tone generator for the sound timer. Produces mono f32 samples in -1.0..=1.0.
+// While an XO-CHIP audio pattern is set it is played instead of the waveform.
+#[derive(Clone, Debug)]
+pub struct Buzzer {
+    pub frequency: f32, // Hz
+    pub waveform: Waveform,
+    pub volume: f32, // 0.0 to 1.0
+    pub muted: bool,
+    sample_rate: u32,
+    phase: f32, // Position within the current period, 0.0 to 1.0
+    pattern: Option<AudioPattern>,
+}
+
+impl Buzzer {
+    pub fn new(sample_rate: u32) -> Buzzer {
+        Buzzer {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            muted: false,
+            sample_rate,
+            phase: 0.0,
+            pattern: None,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn pattern(&self) -> Option<AudioPattern> {
+        self.pattern
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    // Number of samples covering one 60 Hz frame.
+    pub fn samples_per_frame(&self) -> usize {
+        (self.sample_rate / TIMER_FREQUENCY) as usize
+    }
+
+    // Fill `out` with the tone if `active`, or with silence otherwise. The
+    // phase carries over between calls so consecutive buffers join seamlessly.
+    pub fn render(&mut self, active: bool, out: &mut [f32]) {
+        let frequency = match self.pattern {
+            Some(pattern) => pattern.sample_rate() / 128.0,
+            None => self.frequency,
+        };
+        let step = frequency / self.sample_rate as f32;
+        for sample in out.iter_mut() {
+            *sample = if active && !self.muted {
+                self.volume * self.sample_at(self.phase)
+            } else {
+                0.0
+            };
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    fn sample_at(&self, phase: f32) -> f32 {
+        if let Some(pattern) = self.pattern {
+            let bit = (phase * 128.0) as usize;
+            return if pattern.buffer[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                1.0
+            } else {
+                -1.0
+            };
+        }
+        match self.waveform {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+impl Default for Buzzer {
+    fn default() -> Self {
+        Buzzer::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+// Destination for buzzer output, fed once per 60 Hz frame.
+pub trait AudioSink {
+    fn frame(&mut self, buzzer: &mut Buzzer, active: bool);
+}
+
+// Collects the rendered samples in memory, e.g. for tests or offline rendering.
+#[derive(Clone, Debug, Default)]
+pub struct SampleBuffer {
+    pub samples: Vec<f32>,
+}
+
+impl AudioSink for SampleBuffer {
+    fn frame(&mut self, buzzer: &mut Buzzer, active: bool) {
+        let start = self.samples.len();
+        self.samples.resize(start + buzzer.samples_per_frame(), 0.0);
+        buzzer.render(active, &mut self.samples[start..]);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::cpu::CPU;
+use crate::error::{EmulatorError, TraceError};
+use crate::instruction::decode;
+use std::fmt;
+use std::io::BufRead;
+
+// A value recorded in a trace line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Cycle,
+    PC,
+    Opcode,
+    V(u8),
+    I,
+    SP,
+    Delay,
+    Sound,
+}
+
+impl Field {
+    fn parse(key: &str) -> Option<Field> {
+        let field = match key.to_ascii_lowercase().as_str() {
+            "cycle" => Field::Cycle,
+            "pc" => Field::PC,
+            "op" => Field::Opcode,
+            "i" => Field::I,
+            "sp" => Field::SP,
+            "dt" => Field::Delay,
+            "st" => Field::Sound,
+            key => match u8::from_str_radix(key.strip_prefix('v')?, 16) {
+                Ok(x) if x < 16 => Field::V(x),
+                _ => return None,
+            },
+        };
+        Some(field)
+    }
+
+    // Cycle and stack depth are decimal, everything else is hex.
+    fn radix(self) -> u32 {
+        match self {
+            Field::Cycle | Field::SP => 10,
+            _ => 16,
+        }
+    }
+
+    fn value(self, cpu: &CPU, cycle: u64, opcode: u16) -> u64 {
+        match self {
+            Field::Cycle => cycle,
+            Field::PC => cpu.pc() as u64,
+            Field::Opcode => opcode as u64,
+            Field::V(x) => cpu.v()[x as usize] as u64,
+            Field::I => cpu.i() as u64,
+            Field::SP => cpu.sp() as u64,
+            Field::Delay => cpu.delay_timer() as u64,
+            Field::Sound => cpu.sound_timer() as u64,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Cycle => write!(f, "cycle"),
+            Field::PC => write!(f, "pc"),
+            Field::Opcode => write!(f, "op"),
+            Field::V(x) => write!(f, "v{:X}", x),
+            Field::I => write!(f, "i"),
+            Field::SP => write!(f, "sp"),
+            Field::Delay => write!(f, "dt"),
+            Field::Sound => write!(f, "st"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub field: Field,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.field.radix() == 10 {
+            write!(
+                f,
+                "{}: expected {}, got {}",
+                self.field, self.expected, self.actual
+            )
+        } else {
+            write!(
+                f,
+                "{}: expected {:X}, got {:X}",
+                self.field, self.expected, self.actual
+            )
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Mismatch {
+    State(Vec<Difference>),
+    Halted, // The program exited before the end of the reference
+    Error(EmulatorError),
+}
+
+// Where the emulator first disagreed with the reference trace. `pc` and
+// `opcode` are the instruction about to execute, `previous` the one that
+// executed last and probably caused the difference.
+#[derive(Debug)]
+pub struct Divergence {
+    pub line: usize,
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub previous: Option<(u16, u16)>,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "diverged from reference line {} at cycle {}, pc={:04X} op={:04X} ; {}",
+            self.line,
+            self.cycle,
+            self.pc,
+            self.opcode,
+            decode(self.opcode)
+        )?;
+        if let Some((pc, opcode)) = self.previous {
+            writeln!(
+                f,
+                "after pc={:04X} op={:04X} ; {}",
+                pc,
+                opcode,
+                decode(opcode)
+            )?;
+        }
+        match &self.mismatch {
+            Mismatch::State(differences) => {
+                for difference in differences {
+                    writeln!(f, "  {}", difference)?;
+                }
+            }
+            Mismatch::Halted => writeln!(f, "  the program exited")?,
+            Mismatch::Error(err) => writeln!(f, "  {}", err)?,
+        }
+        Ok(())
+    }
+}
+
+// Parse a trace line in the format of write_trace_line: whitespace separated
+// KEY=VALUE fields with the keys cycle, pc, op, v0 to vF, i, sp, dt and st,
+// where cycle and sp are decimal and the others hex. Missing fields are not
+// compared, so a reference may record only some of the state. Everything
+// after `;` is ignored, as are empty lines and lines starting with `#`.
+fn parse_line(text: &str) -> Result<Vec<(Field, u64)>, String> {
+    let text = text.split(';').next().unwrap_or("");
+    text.split_whitespace()
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or(format!("expected KEY=VALUE, found {}", pair))?;
+            let field = Field::parse(key).ok_or(format!("unknown field {}", key))?;
+            let value = u64::from_str_radix(value, field.radix())
+                .map_err(|_| format!("invalid value for {}: {}", key, value))?;
+            Ok((field, value))
+        })
+        .collect()
+}
+
+// Run `cpu` and compare its state before every executed instruction with the
+// next line of `reference`, as written by TraceLog. Timers tick after every
+// `instructions_per_frame` instructions. Returns the number of instructions
+// compared, or where the state first differed.
+pub fn compare_trace<R: BufRead>(
+    cpu: &mut CPU,
+    reference: R,
+    instructions_per_frame: u32,
+) -> Result<Result<u64, Divergence>, TraceError> {
+    let instructions_per_frame = instructions_per_frame.max(1);
+    let mut frame_cycles = 0;
+    let mut cycle = 0;
+    let mut previous = None;
+    for (number, line) in reference.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let expected = parse_line(&line).map_err(|message| TraceError::Syntax {
+            line: number + 1,
+            message,
+        })?;
+
+        let mut step = |cpu: &mut CPU| {
+            let result = cpu.step();
+            frame_cycles += 1;
+            if frame_cycles == instructions_per_frame {
+                frame_cycles = 0;
+                cpu.vblank();
+            }
+            result
+        };
+        while cpu.is_waiting_for_vblank() {
+            // Cannot fail, the CPU does nothing until the vblank
+            let _ = step(cpu);
+        }
+
+        let pc = cpu.pc();
+        let opcode = cpu
+            .memory()
+            .get(pc as usize..pc as usize + 2)
+            .map_or(0, |bytes| (bytes[0] as u16) << 8 | bytes[1] as u16);
+        let divergence = |mismatch| Divergence {
+            line: number + 1,
+            cycle,
+            pc,
+            opcode,
+            previous,
+            mismatch,
+        };
+        if cpu.is_halted() {
+            return Ok(Err(divergence(Mismatch::Halted)));
+        }
+        let differences: Vec<Difference> = expected
+            .iter()
+            .map(|&(field, expected)| Difference {
+                field,
+                expected,
+                actual: field.value(cpu, cycle, opcode),
+            })
+            .filter(|difference| difference.expected != difference.actual)
+            .collect();
+        if !differences.is_empty() {
+            return Ok(Err(divergence(Mismatch::State(differences))));
+        }
+        if let Err(err) = step(cpu) {
+            return Ok(Err(divergence(Mismatch::Error(err))));
+        }
+        previous = Some((pc, opcode));
+        cycle += 1;
+    }
+    Ok(Ok(cycle))
+}
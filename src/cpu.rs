@@ -0,0 +1,1093 @@
+use crate::display::{Framebuffer, Resolution, PLANE_COUNT};
+use crate::error::{EmulatorError, ErrorAction, ErrorPolicy, StateError};
+use crate::instruction::{decode, Instruction};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::random::{Random, RandomAlgorithm};
+use crate::state::{StateReader, StateWriter};
+use crate::trace::TraceSink;
+use std::fs;
+use std::ops::Range;
+
+pub const TIMER_FREQUENCY: u32 = 60; // Hz, the rate of vblank() calls
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+pub const MEMORY_SIZE: usize = 0x10000; // XO-CHIP addresses 64 KiB
+pub const PROGRAM_START: usize = 0x200;
+const HIRES_START: u16 = 0x2C0;
+
+// XO-CHIP audio: a 128 bit sample loop played back at a rate set by the pitch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub buffer: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    // Playback rate in bits per second, 4000 Hz at the default pitch of 64.
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU {
+    memory: [u8; MEMORY_SIZE],
+    v: [u8; 16],
+    i: u16,  // Index register
+    pc: u16, // Program counter
+    graphics: Framebuffer,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [u16; 16],
+    sp: u16, // Stack pointer
+    key: [bool; 16],
+    released: u16,         // Keys released since FX0A started waiting, bit K for key K
+    waiting_for_key: bool, // FX0A is waiting for a key release
+    draw_flag: bool,
+    rpl_user_flags: [u8; 16],
+    is_extended: bool,
+    halted: bool,
+    quirks: Quirks,
+    waiting_for_vblank: bool,
+    beeping: bool,
+    planes: u8,                     // XO-CHIP bitplanes selected for drawing
+    audio_buffer: Option<[u8; 16]>, // XO-CHIP audio pattern, None for the plain buzzer
+    pitch: u8,                      // XO-CHIP audio pitch
+    error_policy: ErrorPolicy,
+    memory_read: Option<Range<usize>>, // Data read by the last instruction
+    memory_written: Option<Range<usize>>, // Data written by the last instruction
+    trace_sink: Option<Box<dyn TraceSink>>,
+    rng: Random, // Source of CXNN
+    seed: u64,   // The seed rng started from
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU::new()
+    }
+}
+
+impl CPU {
+    pub fn new() -> CPU {
+        let chip8_fontset = [
+            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+            0x20, 0x60, 0x20, 0x20, 0x70, // 1
+            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+            0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+            0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+            0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+            0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+            0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+            0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        ];
+
+        let mut memory = [0; MEMORY_SIZE];
+
+        memory[..chip8_fontset.len()].copy_from_slice(&chip8_fontset);
+        let seed = rand::random();
+
+        CPU {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            graphics: Framebuffer::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            stack: [0; 16],
+            sp: 0,
+            key: [false; 16],
+            released: 0,
+            waiting_for_key: false,
+            draw_flag: false,
+            rpl_user_flags: [0; 16],
+            is_extended: false,
+            halted: false,
+            quirks: Quirks::default(),
+            waiting_for_vblank: false,
+            beeping: false,
+            planes: 1,
+            audio_buffer: None,
+            pitch: 64,
+            error_policy: ErrorPolicy::default(),
+            memory_read: None,
+            memory_written: None,
+            trace_sink: None,
+            rng: Random::new(RandomAlgorithm::default(), seed),
+            seed,
+        }
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> CPU {
+        CPU {
+            quirks,
+            ..CPU::new()
+        }
+    }
+
+    // Restart the random number generator of CXNN from `seed`, which makes
+    // runs with the same input reproducible. A new CPU uses a random seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Random::new(self.rng.algorithm(), seed);
+        self.seed = seed;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Switch the random number generator, restarting it from the seed.
+    pub fn set_random_algorithm(&mut self, algorithm: RandomAlgorithm) {
+        self.rng = Random::new(algorithm, self.seed);
+    }
+
+    pub fn random_algorithm(&self) -> RandomAlgorithm {
+        self.rng.algorithm()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    // Report every executed instruction to `sink`, or stop tracing with None.
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        self.trace_sink = sink;
+    }
+
+    pub fn load_game(&mut self, filename: &str) -> Result<(), EmulatorError> {
+        let game = fs::read(filename).map_err(|source| EmulatorError::File {
+            path: filename.to_string(),
+            source,
+        })?;
+        self.load_rom(&game)
+    }
+
+    // A ROM starting with a jump to 0x260 is a HiRes CHIP-8 program, which
+    // runs in 64x64 mode from 0x2C0.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        let max = MEMORY_SIZE - PROGRAM_START;
+        if rom.len() > max {
+            return Err(EmulatorError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        if is_hires_rom(rom) {
+            self.graphics.set_resolution(Resolution::Tall);
+            self.pc = HIRES_START;
+        }
+        Ok(())
+    }
+
+    // Execute a single instruction unless the program has exited via 00FD or
+    // is waiting for a vertical blank after a draw. A failing instruction is
+    // handled according to the error policy; the error is only returned if
+    // the policy halts the CPU.
+    pub fn step(&mut self) -> Result<(), EmulatorError> {
+        self.memory_read = None;
+        self.memory_written = None;
+        if self.halted || self.waiting_for_vblank {
+            return Ok(());
+        }
+        let err = match self.emulate_cycle() {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        match self.error_policy.action(&err) {
+            ErrorAction::Halt => {
+                self.halted = true;
+                return Err(err);
+            }
+            ErrorAction::Skip => {}
+            ErrorAction::Log => eprintln!("{}", err),
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    // Run one 60 Hz frame: execute `instructions` instructions, then vblank().
+    // Stops at the first error that halts the CPU.
+    pub fn run_frame(&mut self, instructions: u32) -> Result<(), EmulatorError> {
+        for _ in 0..instructions {
+            self.step()?;
+        }
+        self.vblank();
+        Ok(())
+    }
+
+    // Signal the 60 Hz vertical blank: tick the delay and sound timers and
+    // release a draw that is waiting for it. Must be called TIMER_FREQUENCY
+    // times per second independent of the instruction rate.
+    pub fn vblank(&mut self) {
+        self.waiting_for_vblank = false;
+        self.rng.tick();
+        self.beeping = self.sound_timer > 0;
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        };
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    // The XO-CHIP audio pattern loaded by F002, if any.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_buffer.map(|buffer| AudioPattern {
+            buffer,
+            pitch: self.pitch,
+        })
+    }
+
+    // Whether the buzzer sounded during the frame ended by the last vblank().
+    pub fn is_beeping(&self) -> bool {
+        self.beeping
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Whether step() is waiting for the next vblank() before executing.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    pub fn graphics(&self) -> &Framebuffer {
+        &self.graphics
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn v(&self) -> [u8; 16] {
+        self.v
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    // Return addresses of the active subroutine calls, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Memory read as data by the last step, e.g. by DXYN or FX65.
+    pub fn memory_read(&self) -> Option<Range<usize>> {
+        self.memory_read.clone()
+    }
+
+    // Memory written by the last step, e.g. by FX55 or FX33.
+    pub fn memory_written(&self) -> Option<Range<usize>> {
+        self.memory_written.clone()
+    }
+
+    // Returns whether the screen changed since the last call and resets the flag.
+    pub fn take_draw_flag(&mut self) -> bool {
+        let draw_flag = self.draw_flag;
+        self.draw_flag = false;
+        draw_flag
+    }
+
+    // Releasing a key that was down is remembered for FX0A, so a release
+    // while the program is busy between two instructions is not lost. A press
+    // and release between two calls is, so a frontend that polls the keys
+    // once per frame misses taps shorter than a frame.
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        if self.key[key] && !pressed {
+            self.released |= 1 << key;
+        }
+        self.key[key] = pressed;
+    }
+
+    pub fn keys(&self) -> [bool; 16] {
+        self.key
+    }
+
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        for (key, &pressed) in keys.iter().enumerate() {
+            self.set_key(key, pressed);
+        }
+    }
+
+    // Serialize the machine state: memory, registers, timers, stack, keys,
+    // screen, audio and the random number generator. Quirks, the error
+    // policy and the trace sink are settings of the emulator rather than the
+    // program and are not saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(&self.memory);
+        writer.bytes(&self.v);
+        writer.u16(self.i);
+        writer.u16(self.pc);
+        self.graphics.write_state(&mut writer);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        for &address in &self.stack {
+            writer.u16(address);
+        }
+        writer.u16(self.sp);
+        for &pressed in &self.key {
+            writer.bool(pressed);
+        }
+        writer.u16(self.released);
+        writer.bool(self.waiting_for_key);
+        writer.bytes(&self.rpl_user_flags);
+        writer.bool(self.is_extended);
+        writer.bool(self.halted);
+        writer.bool(self.waiting_for_vblank);
+        writer.bool(self.beeping);
+        writer.u8(self.planes);
+        writer.bool(self.audio_buffer.is_some());
+        writer.bytes(&self.audio_buffer.unwrap_or_default());
+        writer.u8(self.pitch);
+        let (algorithm, state) = self.rng.to_state();
+        writer.u8(algorithm);
+        writer.u64(state);
+        writer.finish()
+    }
+
+    // Restore a state written by save_state. The CPU is left unchanged if
+    // the state is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data)?;
+        let memory = reader.array::<MEMORY_SIZE>()?;
+        let v = reader.array()?;
+        let i = reader.u16()?;
+        let pc = reader.u16()?;
+        if !is_valid_address(pc) {
+            return Err(StateError::Invalid("pc"));
+        }
+        let graphics = Framebuffer::read_state(&mut reader)?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let mut stack = [0; 16];
+        for address in &mut stack {
+            *address = reader.u16()?;
+        }
+        if !stack.iter().all(|&address| is_valid_address(address)) {
+            return Err(StateError::Invalid("stack"));
+        }
+        let sp = reader.u16()?;
+        if sp as usize > stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let mut key = [false; 16];
+        for pressed in &mut key {
+            *pressed = reader.bool()?;
+        }
+        let released = reader.u16()?;
+        let waiting_for_key = reader.bool()?;
+        let rpl_user_flags = reader.array()?;
+        let is_extended = reader.bool()?;
+        let halted = reader.bool()?;
+        let waiting_for_vblank = reader.bool()?;
+        let beeping = reader.bool()?;
+        let planes = reader.u8()?;
+        if planes as usize >= 1 << PLANE_COUNT {
+            return Err(StateError::Invalid("plane mask"));
+        }
+        let has_audio = reader.bool()?;
+        let audio_buffer = reader.array()?;
+        let pitch = reader.u8()?;
+        let (algorithm, state) = (reader.u8()?, reader.u64()?);
+        let rng = Random::from_state(algorithm, state)
+            .ok_or(StateError::Invalid("random number generator"))?;
+        reader.finish()?;
+
+        self.memory = memory;
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.graphics = graphics;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.sp = sp;
+        self.key = key;
+        self.released = released;
+        self.waiting_for_key = waiting_for_key;
+        self.rpl_user_flags = rpl_user_flags;
+        self.is_extended = is_extended;
+        self.halted = halted;
+        self.waiting_for_vblank = waiting_for_vblank;
+        self.beeping = beeping;
+        self.planes = planes;
+        self.audio_buffer = if has_audio { Some(audio_buffer) } else { None };
+        self.pitch = pitch;
+        self.rng = rng;
+        self.memory_read = None;
+        self.memory_written = None;
+        self.draw_flag = true;
+        Ok(())
+    }
+
+    // Execute the instruction at the program counter: fetch, decode, execute.
+    fn emulate_cycle(&mut self) -> Result<(), EmulatorError> {
+        // Leave room for the following instruction so the PC cannot overflow
+        self.memory_range(self.pc as usize, 4)?;
+        let opcode = self.read_opcode(self.pc)?;
+        if let Some(mut sink) = self.trace_sink.take() {
+            sink.trace(self, opcode);
+            self.trace_sink = Some(sink);
+        }
+        self.execute(decode(opcode))
+    }
+
+    // Execute a decoded instruction read at the program counter and advance
+    // the program counter past it. emulate_cycle has checked that the
+    // instruction and the word after it are in memory.
+    fn execute(&mut self, instruction: Instruction) -> Result<(), EmulatorError> {
+        match instruction {
+            Instruction::ClearScreen => {
+                self.graphics.clear(self.planes);
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Clear the screen
+            Instruction::Return => {
+                if self.sp == 0 {
+                    return Err(EmulatorError::StackUnderflow { address: self.pc });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize].wrapping_add(2);
+            } // Return from subroutine
+            Instruction::ScrollDown(n) => {
+                self.graphics
+                    .scroll_down(self.planes, n as usize * self.scroll_scale());
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Scroll display N lines down
+            Instruction::ScrollUp(n) => {
+                self.graphics
+                    .scroll_up(self.planes, n as usize * self.scroll_scale());
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Scroll display N lines up
+            Instruction::ScrollRight => {
+                self.graphics
+                    .scroll_right(self.planes, 4 * self.scroll_scale());
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Scroll right
+            Instruction::ScrollLeft => {
+                self.graphics
+                    .scroll_left(self.planes, 4 * self.scroll_scale());
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Scroll left
+            Instruction::Exit => {
+                self.halted = true;
+                self.pc += 2;
+            } // Exit interpreter
+            Instruction::LowRes => {
+                self.is_extended = false;
+                self.graphics.set_resolution(Resolution::Low);
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Disable extended mode
+            Instruction::HighRes => {
+                self.is_extended = true;
+                self.graphics.set_resolution(Resolution::High);
+                self.draw_flag = true;
+                self.pc += 2;
+            } // Enable extended mode
+            Instruction::Jump(nnn) => {
+                self.pc = nnn;
+            } // Jump to address NNN
+            Instruction::Call(nnn) => {
+                if self.sp as usize == self.stack.len() {
+                    return Err(EmulatorError::StackOverflow { address: self.pc });
+                }
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.pc = nnn;
+            } // Call subroutine at NNN
+            Instruction::SkipEqImm(x, nn) => {
+                self.skip_if(self.v[x as usize] == nn);
+            } // Skip if VX equal to NN
+            Instruction::SkipNeImm(x, nn) => {
+                self.skip_if(self.v[x as usize] != nn);
+            } // Skip if VX not equal to NN
+            Instruction::SkipEqReg(x, y) => {
+                self.skip_if(self.v[x as usize] == self.v[y as usize]);
+            } // Skip if VX equal to VY
+            Instruction::StoreRange(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                let range = self.write_range(self.i as usize, x.abs_diff(y) + 1)?;
+                for (addr, reg) in range.zip(register_range(x, y)) {
+                    self.memory[addr] = self.v[reg];
+                }
+                self.pc += 2;
+            } // Store VX to VY (inclusive, either order) in memory starting at address I
+            Instruction::LoadRange(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                let range = self.read_range(self.i as usize, x.abs_diff(y) + 1)?;
+                for (addr, reg) in range.zip(register_range(x, y)) {
+                    self.v[reg] = self.memory[addr];
+                }
+                self.pc += 2;
+            } // Fill VX to VY (inclusive, either order) with values from memory starting at address I
+            Instruction::SetImm(x, nn) => {
+                self.v[x as usize] = nn;
+                self.pc += 2;
+            } // Set VX to NN
+            Instruction::AddImm(x, nn) => {
+                if x != 0xF {
+                    self.v[x as usize] = self.v[x as usize].wrapping_add(nn);
+                }
+                self.pc += 2;
+            } // Add NN to VX (carry flag not changed)
+            Instruction::Set(x, y) => {
+                self.v[x as usize] = self.v[y as usize];
+                self.pc += 2;
+            } // Set VX to VY
+            Instruction::Or(x, y) => {
+                self.v[x as usize] |= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            } // Set VX to VX or VY
+            Instruction::And(x, y) => {
+                self.v[x as usize] &= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            } // Set VX to VX and VY
+            Instruction::Xor(x, y) => {
+                self.v[x as usize] ^= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            } // Set VX to VX xor VY
+            Instruction::Add(x, y) => {
+                let (result, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = carry as u8;
+                self.pc += 2;
+            } // Add VY to VX. Set VF to 1 if carry, 0 if not.
+            Instruction::Sub(x, y) => {
+                let (result, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = !borrow as u8;
+                self.pc += 2;
+            } // Subtract VY from VX. Set VF to 0 if borrow, 1 if not.
+            Instruction::ShiftRight(x, y) => {
+                let source = self.shift_source(x, y);
+                self.v[x as usize] = source >> 1;
+                self.v[0xF] = source & 0x1;
+                self.pc += 2;
+            } // Shift VX (or VY) right by 1 into VX. Store the shifted out bit in VF.
+            Instruction::SubReverse(x, y) => {
+                let (result, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = !borrow as u8;
+                self.pc += 2;
+            } // Set VX to VY - VX. Set VF to 0 if borrow, 1 if not.
+            Instruction::ShiftLeft(x, y) => {
+                let source = self.shift_source(x, y);
+                self.v[x as usize] = source << 1;
+                self.v[0xF] = source >> 7;
+                self.pc += 2;
+            } // Shift VX (or VY) left by 1 into VX. Store the shifted out bit in VF.
+            Instruction::SkipNeReg(x, y) => {
+                self.skip_if(self.v[x as usize] != self.v[y as usize]);
+            } // Skip if VX not equal to VY
+            Instruction::SetIndex(nnn) => {
+                self.i = nnn;
+                self.pc += 2;
+            } // Set I to address NNN
+            Instruction::JumpOffset(nnn) => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v[(nnn >> 8) as usize]
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn + offset as u16;
+            } // Jump to address NNN + V0 (or XNN + VX)
+            Instruction::Random(x, nn) => {
+                self.v[x as usize] = self.rng.next_byte() & nn;
+                self.pc += 2;
+            } // Set VX to result of rand() & NN
+            Instruction::Draw(x, y, n) => {
+                self.draw_sprite(self.v[x as usize], self.v[y as usize], n)?;
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
+                }
+                self.pc += 2;
+            } // Draw
+            Instruction::SkipKey(x) => {
+                self.skip_if(self.key[self.v[x as usize] as usize & 0xF]);
+            } // Skip if key in VX is pressed
+            Instruction::SkipNotKey(x) => {
+                self.skip_if(!self.key[self.v[x as usize] as usize & 0xF]);
+            } // Skip if key in VX is not pressed
+            Instruction::LongIndex => {
+                self.i = self.read_opcode(self.pc + 2)?;
+                self.pc = self.pc.wrapping_add(4);
+            } // Set I to the 16-bit address NNNN in the next word
+            Instruction::SelectPlanes(n) => {
+                self.planes = n;
+                self.pc += 2;
+            } // Select the bitplanes N for drawing
+            Instruction::LoadAudio => {
+                let mut buffer = [0; 16];
+                let range = self.read_range(self.i as usize, 16)?;
+                buffer.copy_from_slice(&self.memory[range]);
+                self.audio_buffer = Some(buffer);
+                self.pc += 2;
+            } // Load the 16-byte audio pattern from memory at address I
+            Instruction::GetDelay(x) => {
+                self.v[x as usize] = self.delay_timer;
+                self.pc += 2;
+            } // Set VX to delay timer
+            Instruction::WaitKey(x) => {
+                // Like on the COSMAC VIP a key counts once it is released, so
+                // a held key is reported once. Only releases after the wait
+                // started count. The timers keep running meanwhile.
+                if !self.waiting_for_key {
+                    self.waiting_for_key = true;
+                    self.released = 0;
+                } else if self.released != 0 {
+                    self.v[x as usize] = self.released.trailing_zeros() as u8;
+                    self.waiting_for_key = false;
+                    self.pc += 2;
+                }
+            } // Wait for a key press and release, store the key in VX
+            Instruction::SetDelay(x) => {
+                self.delay_timer = self.v[x as usize];
+                self.pc += 2;
+            } // Set delay timer to VX
+            Instruction::SetSound(x) => {
+                self.sound_timer = self.v[x as usize];
+                self.pc += 2;
+            } // Set sound timer to VX
+            Instruction::AddIndex(x) => {
+                if x != 0xF {
+                    self.i = self.i.wrapping_add(self.v[x as usize] as u16);
+                }
+                self.pc += 2;
+            } // Add VX to I if X is not F
+            Instruction::Font(x) => {
+                self.i = self.v[x as usize] as u16 * 5;
+                self.pc += 2;
+            } // Set I to the location of the sprite for the 5-byte character in VX.
+            Instruction::BigFont(x) => {
+                self.i = self.v[x as usize] as u16 * 10 + 80;
+                self.pc += 2;
+            } // Set I to the location of the sprite for the 10-byte character in VX.
+            Instruction::SetPitch(x) => {
+                self.pitch = self.v[x as usize];
+                self.pc += 2;
+            } // Set the audio pitch to VX
+            Instruction::Bcd(x) => {
+                let value = self.v[x as usize];
+                let range = self.write_range(self.i as usize, 3)?;
+                self.memory[range].copy_from_slice(&[value / 100, value / 10 % 10, value % 10]);
+                self.pc += 2;
+            } // Store BCD representation of VX at the address in I
+            Instruction::Store(x) => {
+                let x = x as usize;
+                let range = self.write_range(self.i as usize, x + 1)?;
+                self.memory[range].copy_from_slice(&self.v[..=x]);
+                self.increment_index(x);
+                self.pc += 2;
+            } // Store V0 to VX (inclusive) in memory starting at address I
+            Instruction::Load(x) => {
+                let x = x as usize;
+                let range = self.read_range(self.i as usize, x + 1)?;
+                self.v[..=x].copy_from_slice(&self.memory[range]);
+                self.increment_index(x);
+                self.pc += 2;
+            } // Fill V0 to VX (inclusive) with values from memory starting at address I
+            Instruction::SaveFlags(x) => {
+                let x = x as usize;
+                self.rpl_user_flags[..=x].copy_from_slice(&self.v[..=x]);
+                self.pc += 2;
+            } // Store V0 to VX (inclusive) in RPL user flags
+            Instruction::LoadFlags(x) => {
+                let x = x as usize;
+                self.v[..=x].copy_from_slice(&self.rpl_user_flags[..=x]);
+                self.pc += 2;
+            } // Read V0 to VX (inclusive) from RPL user flags
+            Instruction::Unknown(opcode) => return Err(self.unknown_opcode(opcode)),
+        }
+        Ok(())
+    }
+
+    fn unknown_opcode(&self, opcode: u16) -> EmulatorError {
+        EmulatorError::UnknownOpcode {
+            opcode,
+            address: self.pc,
+        }
+    }
+
+    // The `len` bytes of memory starting at `start`, or an error if they do
+    // not fit in memory.
+    fn memory_range(&self, start: usize, len: usize) -> Result<Range<usize>, EmulatorError> {
+        if start + len > MEMORY_SIZE {
+            return Err(EmulatorError::MemoryOutOfBounds {
+                address: start.max(MEMORY_SIZE),
+                pc: self.pc,
+            });
+        }
+        Ok(start..start + len)
+    }
+
+    // Like memory_range, recording the range as read by this instruction.
+    fn read_range(&mut self, start: usize, len: usize) -> Result<Range<usize>, EmulatorError> {
+        let range = self.memory_range(start, len)?;
+        self.memory_read = Some(range.clone());
+        Ok(range)
+    }
+
+    // Like memory_range, recording the range as written by this instruction.
+    fn write_range(&mut self, start: usize, len: usize) -> Result<Range<usize>, EmulatorError> {
+        let range = self.memory_range(start, len)?;
+        self.memory_written = Some(range.clone());
+        Ok(range)
+    }
+
+    fn read_opcode(&self, addr: u16) -> Result<u16, EmulatorError> {
+        let range = self.memory_range(addr as usize, 2)?;
+        Ok((self.memory[range.start] as u16) << 8 | self.memory[range.start + 1] as u16)
+    }
+
+    // Advance past the current and, if `condition` holds, the next
+    // instruction, which is 4 bytes long if it is the XO-CHIP F000 NNNN.
+    fn skip_if(&mut self, condition: bool) {
+        self.pc += 2;
+        if condition {
+            let size = self
+                .read_opcode(self.pc)
+                .map_or(2, |opcode| decode(opcode).size());
+            self.pc = self.pc.wrapping_add(size);
+        }
+    }
+
+    // The operand of 8XY6 and 8XYE, which depends on the shift quirk.
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y as usize]
+        } else {
+            self.v[x as usize]
+        }
+    }
+
+    fn increment_index(&mut self, x: usize) {
+        match self.quirks.load_store_index {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.i = self.i.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    // Physical pixels per scrolled pixel: scrolling moves by half pixels in
+    // lores mode unless the lores_scroll_full quirk is set.
+    fn scroll_scale(&self) -> usize {
+        if self.quirks.lores_scroll_full && self.graphics.resolution() == Resolution::Low {
+            2
+        } else {
+            1
+        }
+    }
+
+    // XOR a sprite from memory at I onto each selected plane and set VF on
+    // collision. In extended mode, or in lores with the lores_big_sprite
+    // quirk, a height of 0 draws a 16x16 sprite. With several planes
+    // selected, each plane takes the next sprite in memory.
+    fn draw_sprite(&mut self, pos_x: u8, pos_y: u8, height: u8) -> Result<(), EmulatorError> {
+        let width = if height == 0 && (self.is_extended || self.quirks.lores_big_sprite) {
+            16
+        } else {
+            8
+        };
+        let (screen_width, screen_height) = self.graphics.resolution().size();
+        let rows = if width == 16 { 16 } else { height as usize };
+        let bytes_per_row = width / 8;
+        let pos_x = pos_x as usize % screen_width;
+        let pos_y = pos_y as usize % screen_height;
+
+        let planes = self.planes;
+        let sprite_len = rows * bytes_per_row;
+        self.read_range(self.i as usize, planes.count_ones() as usize * sprite_len)?;
+
+        self.v[0xF] = 0;
+        let mut sprite_addr = self.i as usize;
+        for plane in (0..PLANE_COUNT).filter(|plane| planes & (1 << plane) != 0) {
+            for y_line in 0..rows {
+                let row_addr = sprite_addr + y_line * bytes_per_row;
+                let pixels = self.memory[row_addr..row_addr + bytes_per_row]
+                    .iter()
+                    .fold(0u16, |acc, &byte| acc << 8 | byte as u16);
+                for x_line in 0..width {
+                    if pixels & (1 << (width - 1 - x_line)) == 0 {
+                        continue;
+                    }
+                    let (mut px, mut py) = (pos_x + x_line, pos_y + y_line);
+                    if px >= screen_width || py >= screen_height {
+                        if !self.quirks.wrap_sprites {
+                            continue;
+                        }
+                        px %= screen_width;
+                        py %= screen_height;
+                    }
+                    if self.graphics.toggle(plane, px, py) {
+                        self.v[0xF] = 1;
+                    }
+                }
+            }
+            sprite_addr += sprite_len;
+        }
+        self.draw_flag = true;
+        Ok(())
+    }
+}
+
+// Whether an instruction can start at `address`, a program counter or
+// return address in a save state.
+fn is_valid_address(address: u16) -> bool {
+    address as usize <= MEMORY_SIZE - 2
+}
+
+// A ROM starting with a jump to 0x260 is a HiRes CHIP-8 program.
+fn is_hires_rom(rom: &[u8]) -> bool {
+    rom.starts_with(&[0x12, 0x60])
+}
+
+// Where execution of `rom` starts.
+pub(crate) fn entry_point(rom: &[u8]) -> u16 {
+    if is_hires_rom(rom) {
+        HIRES_START
+    } else {
+        PROGRAM_START as u16
+    }
+}
+
+// Registers X to Y inclusive, counting down if X > Y.
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    let count = x.abs_diff(y);
+    (0..=count).map(move |offset| if x <= y { x + offset } else { x - offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Draws random sprites, calls a subroutine and uses the timers, the
+    // keypad and XO-CHIP audio, so that most of the state changes.
+    const PROGRAM: &str = ": main
+        hires
+        i := pattern
+        audio
+        loop
+            v0 := random 0x7F
+            v1 := random 0x3F
+            i := hex v2
+            sprite v0 v1 5
+            v2 := 0x0F
+            if v2 key then step
+            delay := v0
+            buzzer := v1
+        again
+     : step
+        v3 += 1
+        save v3
+        return
+     : pattern 0xF0 0x0F 0xF0 0x0F 0xF0 0x0F 0xF0 0x0F
+        0xF0 0x0F 0xF0 0x0F 0xF0 0x0F 0xF0 0x0F";
+
+    fn running_cpu() -> CPU {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.set_seed(1);
+        cpu.load_rom(&assemble(PROGRAM).unwrap()).unwrap();
+        for frame in 0..50 {
+            cpu.set_key(0xF, frame % 4 == 0);
+            cpu.run_frame(20).unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn loaded_state_runs_like_the_saved_cpu() {
+        let mut cpu = running_cpu();
+        let state = cpu.save_state();
+        // Quirks are options rather than state
+        let mut loaded = CPU::with_quirks(Quirks::XO_CHIP);
+        loaded.load_state(&state).unwrap();
+        assert!(loaded.save_state() == state);
+        for frame in 0..50 {
+            cpu.set_key(0xF, frame % 3 == 0);
+            loaded.set_key(0xF, frame % 3 == 0);
+            cpu.run_frame(20).unwrap();
+            loaded.run_frame(20).unwrap();
+        }
+        assert!(loaded.save_state() == cpu.save_state());
+    }
+
+    fn waiting_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        let rom = assemble("v0 := 30 delay := v0 v1 := key v2 := 1 loop again").unwrap();
+        cpu.load_rom(&rom).unwrap();
+        cpu.run_frame(10).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn wait_key_finishes_on_release() {
+        let mut cpu = waiting_cpu();
+        cpu.set_key(7, true);
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.v()[2], 0);
+        cpu.set_key(7, false);
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.v()[1..3], [7, 1]);
+    }
+
+    #[test]
+    fn wait_key_ignores_earlier_releases() {
+        let mut cpu = CPU::new();
+        let rom = assemble("v1 := key v2 := 1 loop again").unwrap();
+        cpu.load_rom(&rom).unwrap();
+        cpu.set_key(5, true);
+        cpu.set_key(5, false);
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.v()[2], 0);
+
+        // A key held when the wait starts counts once it is released
+        cpu.set_key(4, true);
+        cpu.run_frame(10).unwrap();
+        cpu.set_key(4, false);
+        cpu.run_frame(10).unwrap();
+        assert_eq!(cpu.v()[1..3], [4, 1]);
+    }
+
+    #[test]
+    fn timers_run_while_waiting_for_a_key() {
+        let mut cpu = waiting_cpu();
+        let delay = cpu.delay_timer();
+        for _ in 0..5 {
+            cpu.run_frame(10).unwrap();
+        }
+        assert_eq!(cpu.delay_timer(), delay - 5);
+        assert_eq!(cpu.v()[2], 0);
+    }
+
+    #[test]
+    fn rejects_truncated_states() {
+        let state = running_cpu().save_state();
+        let mut cpu = CPU::new();
+        let before = cpu.save_state();
+        for len in [
+            0,
+            4,
+            6,
+            100,
+            MEMORY_SIZE + 6,
+            state.len() - 9,
+            state.len() - 1,
+        ] {
+            let expected = if len < 4 {
+                StateError::NotAState
+            } else {
+                StateError::Truncated
+            };
+            assert_eq!(cpu.load_state(&state[..len]), Err(expected), "{}", len);
+        }
+        assert!(cpu.save_state() == before);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let mut state = running_cpu().save_state();
+        state.push(0);
+        assert_eq!(
+            CPU::new().load_state(&state),
+            Err(StateError::Invalid("trailing data"))
+        );
+
+        let mut cpu = running_cpu();
+        cpu.pc = (MEMORY_SIZE - 1) as u16;
+        assert_eq!(
+            CPU::new().load_state(&cpu.save_state()),
+            Err(StateError::Invalid("pc"))
+        );
+
+        let mut cpu = running_cpu();
+        cpu.stack[15] = 0xFFFF;
+        assert_eq!(
+            CPU::new().load_state(&cpu.save_state()),
+            Err(StateError::Invalid("stack"))
+        );
+
+        let mut cpu = running_cpu();
+        cpu.sp = 17;
+        assert_eq!(
+            CPU::new().load_state(&cpu.save_state()),
+            Err(StateError::Invalid("stack pointer"))
+        );
+
+        let mut cpu = running_cpu();
+        cpu.planes = 0x10;
+        assert_eq!(
+            CPU::new().load_state(&cpu.save_state()),
+            Err(StateError::Invalid("plane mask"))
+        );
+
+        let mut state = running_cpu().save_state();
+        let algorithm = state.len() - 9;
+        state[algorithm] = 0xFF;
+        assert_eq!(
+            CPU::new().load_state(&state),
+            Err(StateError::Invalid("random number generator"))
+        );
+
+        let mut state = running_cpu().save_state();
+        state[4] = 0xFF;
+        assert_eq!(
+            CPU::new().load_state(&state),
+            Err(StateError::UnsupportedVersion(0xFF))
+        );
+    }
+}
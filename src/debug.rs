@@ -0,0 +1,328 @@
+use crate::headless;
+use chip8::{decode, Access, Debugger, Instruction, Stop, Watchpoint, CPU, MEMORY_SIZE};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+const HELP: &str = "commands:
+    step [N], s       execute N instructions (default 1), stopping at breakpoints
+    next, n           execute one instruction, stepping over subroutine calls
+    finish, f         run until the current subroutine returns
+    continue, c       run until a breakpoint, press Enter to pause
+    break ADDR, b     set a breakpoint at the hex address ADDR
+    delete ADDR, d    remove the breakpoint at ADDR
+    watch [r|w] ADDR[..END]
+                      pause when an instruction reads (r), writes (w) or
+                      accesses the memory from ADDR up to END (exclusive)
+    cond EXPR         pause when EXPR becomes true, e.g. v3 == 0x10 or
+                      i outside 0x200..0x1000, with v0-vF, i, pc, sp,
+                      delay, sound and ==, !=, <, <=, >, >=, inside, outside
+    unwatch N         remove watchpoint number N
+    uncond N          remove condition number N
+    breaks            list breakpoints, watchpoints and conditions
+    regs, r           show registers and timers
+    stack             show the call stack
+    mem ADDR [LEN]    dump LEN bytes of memory from ADDR (default 64)
+    dis [ADDR] [N]    disassemble N instructions from ADDR (default PC, 8)
+    screen            print the display
+    quit, q           exit the debugger
+An empty line repeats the last command.";
+
+// Read commands from stdin on a separate thread so that a running program
+// can be paused by pressing Enter.
+fn spawn_input() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let sent = line.map(|line| sender.send(line).is_ok());
+            if sent.ok() != Some(true) {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+// The instruction at `address` in Octo syntax and its size in bytes.
+fn disassemble(cpu: &CPU, address: u16) -> (String, u16) {
+    let word = |address: u16| {
+        let memory = cpu.memory();
+        let address = address as usize;
+        match memory.get(address..address + 2) {
+            Some(&[high, low]) => Some((high as u16) << 8 | low as u16),
+            _ => None,
+        }
+    };
+    let opcode = match word(address) {
+        Some(opcode) => opcode,
+        None => return ("(end of memory)".to_string(), 2),
+    };
+    let instruction = decode(opcode);
+    let text = match (instruction, word(address.wrapping_add(2))) {
+        (Instruction::LongIndex, Some(nnnn)) => {
+            format!("{:04X} {:04X}  i := long 0x{:04X}", opcode, nnnn, nnnn)
+        }
+        _ => format!("{:04X}       {}", opcode, instruction),
+    };
+    (text, instruction.size())
+}
+
+fn print_location(cpu: &CPU) {
+    println!("{:04X}  {}", cpu.pc(), disassemble(cpu, cpu.pc()).0);
+}
+
+fn print_registers(cpu: &CPU) {
+    let v = cpu.v();
+    for (row, registers) in v.chunks(8).enumerate() {
+        let line: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(index, value)| format!("v{:X}={:02X}", row * 8 + index, value))
+            .collect();
+        println!("{}", line.join(" "));
+    }
+    println!(
+        "pc={:04X} i={:04X} sp={} delay={} sound={}",
+        cpu.pc(),
+        cpu.i(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+}
+
+fn print_memory(cpu: &CPU, start: u16, len: usize) {
+    let memory = cpu.memory();
+    let start = (start as usize).min(memory.len());
+    let end = start.saturating_add(len).min(memory.len());
+    for row in (start..end).step_by(16) {
+        let bytes: Vec<String> = memory[row..(row + 16).min(end)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:04X}  {}", row, bytes.join(" "));
+    }
+}
+
+// A watchpoint from `[r|w] ADDR[..END]` with hex addresses. END may be
+// 10000 to include the last byte.
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let usage = || "usage: watch [r|w] ADDR[..END]".to_string();
+    let (access, range) = match args {
+        ["r", range] => (Access::Read, range),
+        ["w", range] => (Access::Write, range),
+        ["rw", range] | [range] => (Access::ReadWrite, range),
+        _ => return Err(usage()),
+    };
+    let range = match range.split_once("..") {
+        Some((start, end)) => {
+            let start = parse_address(start).ok_or_else(usage)? as usize;
+            let end = usize::from_str_radix(end.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&end| end <= MEMORY_SIZE)
+                .ok_or_else(usage)?;
+            if start >= end {
+                return Err(format!("empty range: {:04X}..{:04X}", start, end));
+            }
+            start..end
+        }
+        None => {
+            let address = parse_address(range).ok_or_else(usage)? as usize;
+            address..address + 1
+        }
+    };
+    Ok(Watchpoint { range, access })
+}
+
+fn report(stop: Stop, cpu: &CPU) {
+    match stop {
+        Stop::Done => {}
+        Stop::Breakpoint(address) => println!("breakpoint at {:04X}", address),
+        Stop::Watchpoint {
+            pc,
+            address,
+            access,
+        } => println!("{} of {:04X} by instruction at {:04X}", access, address, pc),
+        Stop::Condition(condition) => println!("condition {} became true", condition),
+        Stop::Halted => println!("program exited"),
+        Stop::Interrupted => println!("paused"),
+        Stop::Error(err) => println!("error: {}", err),
+    }
+    print_location(cpu);
+}
+
+// Run `cpu` under an interactive debugger on the terminal, starting paused.
+pub fn run(cpu: &mut CPU, instructions_per_frame: u32) -> io::Result<()> {
+    let mut debugger = Debugger::new(instructions_per_frame);
+    let input = spawn_input();
+    // A line of input pauses, as does closing the input which could not
+    // pause it anymore
+    let mut interrupted = || !matches!(input.try_recv(), Err(TryRecvError::Empty));
+    let mut last = String::new();
+    println!("type help for a list of commands");
+    print_location(cpu);
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush()?;
+        let line = match input.recv() {
+            Ok(line) => line,
+            Err(_) => return Ok(()),
+        };
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line
+        };
+        last = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+        match command {
+            "step" | "s" => {
+                let count = args.first().and_then(|n| n.parse().ok()).unwrap_or(1);
+                report(debugger.step_many(cpu, count, &mut interrupted), cpu);
+            }
+            "next" | "n" => report(debugger.step_over(cpu, &mut interrupted), cpu),
+            "finish" | "f" => {
+                if cpu.sp() == 0 {
+                    println!("not in a subroutine");
+                } else {
+                    report(debugger.step_out(cpu, &mut interrupted), cpu);
+                }
+            }
+            "continue" | "c" => report(debugger.resume(cpu, &mut interrupted), cpu),
+            "break" | "b" | "delete" | "d" => {
+                let address = match args.first().and_then(|arg| parse_address(arg)) {
+                    Some(address) => address,
+                    None => {
+                        println!("{} needs a hex address", command);
+                        continue;
+                    }
+                };
+                if command.starts_with('b') {
+                    if debugger.add_breakpoint(address) {
+                        println!("breakpoint at {:04X}", address);
+                    }
+                } else if !debugger.remove_breakpoint(address) {
+                    println!("no breakpoint at {:04X}", address);
+                }
+            }
+            "watch" | "w" => match parse_watchpoint(args) {
+                Ok(watchpoint) => debugger.add_watchpoint(watchpoint),
+                Err(err) => println!("{}", err),
+            },
+            "cond" => match args.join(" ").parse() {
+                Ok(condition) => debugger.add_condition(condition, cpu),
+                Err(err) => println!("{}", err),
+            },
+            "unwatch" | "uncond" => {
+                let index = match args.first().and_then(|n| n.parse().ok()) {
+                    Some(index) => index,
+                    None => {
+                        println!("{} needs a number from breaks", command);
+                        continue;
+                    }
+                };
+                let removed = if command == "unwatch" {
+                    debugger.remove_watchpoint(index).is_some()
+                } else {
+                    debugger.remove_condition(index).is_some()
+                };
+                if !removed {
+                    println!("no such {}", &command[2..]);
+                }
+            }
+            "breaks" => {
+                for address in debugger.breakpoints() {
+                    println!("break {:04X}", address);
+                }
+                for (index, watchpoint) in debugger.watchpoints().iter().enumerate() {
+                    println!("watch #{} {}", index, watchpoint);
+                }
+                for (index, condition) in debugger.conditions().enumerate() {
+                    println!("cond #{} {}", index, condition);
+                }
+            }
+            "regs" | "r" => print_registers(cpu),
+            "stack" => {
+                for (depth, address) in cpu.stack().iter().enumerate().rev() {
+                    println!("#{} {:04X}", depth, address);
+                }
+            }
+            "mem" | "m" => match args.first().and_then(|arg| parse_address(arg)) {
+                Some(start) => {
+                    let len = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(64);
+                    print_memory(cpu, start, len);
+                }
+                None => println!("mem needs a hex address"),
+            },
+            "dis" => {
+                let mut address = args
+                    .first()
+                    .and_then(|arg| parse_address(arg))
+                    .unwrap_or_else(|| cpu.pc());
+                let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(8);
+                for _ in 0..count {
+                    let (text, size) = disassemble(cpu, address);
+                    println!("{:04X}  {}", address, text);
+                    address = address.wrapping_add(size);
+                }
+            }
+            "screen" => headless::write_text(cpu, &mut io::stdout())?,
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(()),
+            _ => println!("unknown command {}, type help for a list", command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_watchpoints() {
+        let watch = |args: &[&str]| parse_watchpoint(args);
+        assert_eq!(
+            watch(&["0x300"]),
+            Ok(Watchpoint {
+                range: 0x300..0x301,
+                access: Access::ReadWrite
+            })
+        );
+        assert_eq!(
+            watch(&["r", "300..310"]),
+            Ok(Watchpoint {
+                range: 0x300..0x310,
+                access: Access::Read
+            })
+        );
+        assert_eq!(
+            watch(&["w", "ff00..10000"]),
+            Ok(Watchpoint {
+                range: 0xFF00..MEMORY_SIZE,
+                access: Access::Write
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_watchpoints() {
+        let usage = Err("usage: watch [r|w] ADDR[..END]".to_string());
+        assert_eq!(parse_watchpoint(&[]), usage);
+        assert_eq!(parse_watchpoint(&["x", "300"]), usage);
+        assert_eq!(parse_watchpoint(&["zz"]), usage);
+        assert_eq!(parse_watchpoint(&["300..10001"]), usage);
+        assert_eq!(
+            parse_watchpoint(&["310..300"]),
+            Err("empty range: 0310..0300".to_string())
+        );
+        assert!(parse_watchpoint(&["300..300"]).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::cpu::CPU;
+use crate::error::EmulatorError;
+use crate::instruction::{decode, Instruction};
+use crate::watch::{Access, Condition, Watchpoint};
+use std::collections::BTreeSet;
+
+// Why the debugger stopped running the program.
+#[derive(Debug)]
+pub enum Stop {
+    Done,            // The requested step completed
+    Breakpoint(u16), // The program counter reached a breakpoint
+    Watchpoint {
+        pc: u16, // Address of the accessing instruction
+        address: usize,
+        access: Access,
+    },
+    Condition(Condition), // The condition became true
+    Halted,               // The program exited via 00FD
+    Interrupted,          // The caller asked to pause
+    Error(EmulatorError),
+}
+
+// Runs a CPU instruction by instruction with breakpoints, watchpoints and
+// conditional breaks, ticking the timers after every `instructions_per_frame`
+// instructions like the normal frame loop.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<(Condition, bool)>, // Each with whether it held after the last step
+    instructions_per_frame: u32,
+    frame_cycles: u32,
+}
+
+impl Debugger {
+    pub fn new(instructions_per_frame: u32) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            instructions_per_frame: instructions_per_frame.max(1),
+            frame_cycles: 0,
+        }
+    }
+
+    // Returns false if there already was a breakpoint at `address`.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    // Returns false if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Break whenever `condition` becomes true. It does not break right away
+    // if it already holds for `cpu`.
+    pub fn add_condition(&mut self, condition: Condition, cpu: &CPU) {
+        self.conditions.push((condition, condition.holds(cpu)));
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> Option<Condition> {
+        if index < self.conditions.len() {
+            Some(self.conditions.remove(index).0)
+        } else {
+            None
+        }
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = Condition> + '_ {
+        self.conditions.iter().map(|&(condition, _)| condition)
+    }
+
+    // Execute a single instruction.
+    pub fn step(&mut self, cpu: &mut CPU) -> Stop {
+        if cpu.is_halted() {
+            return Stop::Halted;
+        }
+        let pc = cpu.pc();
+        if let Err(err) = cpu.step() {
+            return Stop::Error(err);
+        }
+        self.frame_cycles += 1;
+        if self.frame_cycles == self.instructions_per_frame {
+            self.frame_cycles = 0;
+            cpu.vblank();
+        }
+
+        let mut stop = Stop::Done;
+        for (condition, held) in &mut self.conditions {
+            let holds = condition.holds(cpu);
+            if holds && !*held {
+                stop = Stop::Condition(*condition);
+            }
+            *held = holds;
+        }
+        if let Some((address, access)) = self.watchpoints.iter().find_map(|watch| watch.hit(cpu)) {
+            stop = Stop::Watchpoint {
+                pc,
+                address,
+                access,
+            };
+        }
+        stop
+    }
+
+    // Execute `count` instructions, stopping early like resume.
+    pub fn step_many(
+        &mut self,
+        cpu: &mut CPU,
+        count: u64,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> Stop {
+        if count == 0 {
+            return Stop::Done;
+        }
+        let mut remaining = count;
+        self.run_until(cpu, interrupted, |_| {
+            remaining -= 1;
+            remaining == 0
+        })
+    }
+
+    // Execute a single instruction, running a called subroutine to its return.
+    pub fn step_over(&mut self, cpu: &mut CPU, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        let opcode = match cpu.memory().get(cpu.pc() as usize..cpu.pc() as usize + 2) {
+            Some(&[high, low]) => (high as u16) << 8 | low as u16,
+            _ => return self.step(cpu),
+        };
+        if let Instruction::Call(_) = decode(opcode) {
+            let (return_address, depth) = (cpu.pc().wrapping_add(2), cpu.sp());
+            self.run_until(cpu, interrupted, |cpu| {
+                cpu.pc() == return_address && cpu.sp() == depth
+            })
+        } else {
+            self.step(cpu)
+        }
+    }
+
+    // Run until the current subroutine returns.
+    pub fn step_out(&mut self, cpu: &mut CPU, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        let depth = cpu.sp();
+        if depth == 0 {
+            return Stop::Error(EmulatorError::StackUnderflow { address: cpu.pc() });
+        }
+        self.run_until(cpu, interrupted, |cpu| cpu.sp() < depth)
+    }
+
+    // Run until a breakpoint is reached or the program exits.
+    pub fn resume(&mut self, cpu: &mut CPU, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        self.run_until(cpu, interrupted, |_| false)
+    }
+
+    // Step until `done` holds after an instruction or the program counter
+    // reaches a breakpoint, ignoring one at the starting address so that a
+    // stopped program can continue. `interrupted` is polled once per frame.
+    fn run_until(
+        &mut self,
+        cpu: &mut CPU,
+        interrupted: &mut dyn FnMut() -> bool,
+        mut done: impl FnMut(&CPU) -> bool,
+    ) -> Stop {
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&cpu.pc()) {
+                return Stop::Breakpoint(cpu.pc());
+            }
+            first = false;
+            match self.step(cpu) {
+                Stop::Done => {}
+                stop => return stop,
+            }
+            if done(cpu) {
+                return Stop::Done;
+            }
+            if self.frame_cycles == 0 && interrupted() {
+                return Stop::Interrupted;
+            }
+        }
+    }
+}
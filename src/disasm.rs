@@ -0,0 +1,299 @@
+use crate::cpu::{entry_point, PROGRAM_START};
+use crate::instruction::{decode, Instruction};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+
+// Write a linear listing of `rom` as loaded at PROGRAM_START, one line per
+// instruction with its address, raw opcode and Octo mnemonic. A trailing odd
+// byte is listed as data.
+pub fn write_listing<W: Write>(out: &mut W, rom: &[u8]) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset;
+        if offset + 1 == rom.len() {
+            writeln!(
+                out,
+                "{:04X}  {:02X}         0x{:02X}",
+                address, rom[offset], rom[offset]
+            )?;
+            break;
+        }
+        let opcode = read_word(rom, offset);
+        match decode(opcode) {
+            Instruction::LongIndex if offset + 4 <= rom.len() => {
+                let nnnn = read_word(rom, offset + 2);
+                writeln!(
+                    out,
+                    "{:04X}  {:04X} {:04X}  i := long 0x{:04X}",
+                    address, opcode, nnnn, nnnn
+                )?;
+                offset += 4;
+            }
+            Instruction::LongIndex => {
+                let data = Instruction::Unknown(opcode);
+                writeln!(out, "{:04X}  {:04X}       {}", address, opcode, data)?;
+                offset += 2;
+            }
+            instruction => {
+                writeln!(out, "{:04X}  {:04X}       {}", address, opcode, instruction)?;
+                offset += 2;
+            }
+        }
+    }
+    Ok(())
+}
+
+// What a ROM byte was found to be by following the control flow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Byte {
+    Unknown, // Never executed or drawn, listed as raw data
+    Code,    // First byte of an instruction
+    Operand, // Remaining bytes of an instruction
+    Sprite,  // Drawn by DXYN
+}
+
+// Why an address is labelled, in increasing order of precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Label {
+    Data, // Loaded into I
+    Code, // Target of a jump
+    Sub,  // Target of a call
+    Main, // Entry point
+}
+
+struct Analysis<'a> {
+    rom: &'a [u8],
+    bytes: Vec<Byte>,
+    labels: BTreeMap<usize, Label>,
+}
+
+// Write `rom` as Octo source that assembles back to the same bytes. Code is
+// found by following jumps, calls and skips from the entry point, bytes drawn
+// by DXYN after a known ANNN are listed as sprite data, and everything else
+// as raw bytes.
+pub fn write_disassembly<W: Write>(out: &mut W, rom: &[u8]) -> io::Result<()> {
+    let analysis = Analysis::new(rom);
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset;
+        if let Some(name) = analysis.label_name(address) {
+            writeln!(out, ": {}", name)?;
+        }
+        match analysis.bytes[offset] {
+            Byte::Code => {
+                let line = analysis.mnemonic(offset);
+                let size = decode(read_word(rom, offset)).size() as usize;
+                let raw = rom[offset..offset + size]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<String>();
+                writeln!(out, "    {:<28}# {:04X}  {}", line, address, raw)?;
+                offset += size;
+            }
+            Byte::Sprite => {
+                writeln!(out, "    0b{:08b}", rom[offset])?;
+                offset += 1;
+            }
+            Byte::Operand | Byte::Unknown => {
+                // Up to 8 bytes per line, starting a new line at labels.
+                let mut end = offset + 1;
+                while end < rom.len()
+                    && end - offset < 8
+                    && analysis.bytes[end] == Byte::Unknown
+                    && !analysis.labels.contains_key(&(PROGRAM_START + end))
+                {
+                    end += 1;
+                }
+                let data = rom[offset..end]
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect::<Vec<_>>();
+                writeln!(out, "    {}", data.join(" "))?;
+                offset = end;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Analysis<'a> {
+    fn new(rom: &'a [u8]) -> Self {
+        let mut analysis = Analysis {
+            rom,
+            bytes: vec![Byte::Unknown; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        analysis.trace(entry_point(rom) as usize);
+        analysis
+    }
+
+    // Follow every path from `entry` and mark the instructions and sprites
+    // met on the way. Each path tracks the value of I while it is known.
+    fn trace(&mut self, entry: usize) {
+        // Octo places `main` first, so only name the usual entry point main.
+        let label = if entry == PROGRAM_START {
+            Label::Main
+        } else {
+            Label::Code
+        };
+        self.labels.insert(entry, label);
+        let mut pending = vec![(entry, None)];
+        let mut visited = HashSet::new();
+        while let Some((address, index)) = pending.pop() {
+            // Revisit code when I differs so every sprite it draws is found
+            if !visited.insert((address, index)) {
+                continue;
+            }
+            let offset = match self.offset(address, 2) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            if self.bytes[offset] == Byte::Operand {
+                continue;
+            }
+            let instruction = decode(read_word(self.rom, offset));
+            let size = instruction.size() as usize;
+            if let Instruction::Unknown(_) = instruction {
+                continue;
+            }
+            if self.offset(address, size).is_none() {
+                continue;
+            }
+            // Overlapping an instruction found on another path
+            let overlaps = self.bytes[offset + 1..offset + size]
+                .iter()
+                .any(|&byte| byte == Byte::Code || byte == Byte::Operand);
+            if overlaps {
+                continue;
+            }
+            self.bytes[offset] = Byte::Code;
+            for byte in &mut self.bytes[offset + 1..offset + size] {
+                *byte = Byte::Operand;
+            }
+
+            let next = address + size;
+            match instruction {
+                Instruction::Jump(nnn) => {
+                    self.label(nnn as usize, Label::Code);
+                    pending.push((nnn as usize, index));
+                }
+                Instruction::Call(nnn) => {
+                    self.label(nnn as usize, Label::Sub);
+                    pending.push((nnn as usize, None));
+                    pending.push((next, None));
+                }
+                Instruction::JumpOffset(nnn) => {
+                    self.label(nnn as usize, Label::Code);
+                    pending.push((nnn as usize, None));
+                }
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::SkipEqImm(..)
+                | Instruction::SkipNeImm(..)
+                | Instruction::SkipEqReg(..)
+                | Instruction::SkipNeReg(..)
+                | Instruction::SkipKey(_)
+                | Instruction::SkipNotKey(_) => {
+                    pending.push((next, index));
+                    let skipped = self.offset(next, 2).map_or(2, |offset| {
+                        decode(read_word(self.rom, offset)).size() as usize
+                    });
+                    pending.push((next + skipped, index));
+                }
+                Instruction::SetIndex(nnn) => {
+                    self.label(nnn as usize, Label::Data);
+                    pending.push((next, Some(nnn as usize)));
+                }
+                Instruction::LongIndex => {
+                    let nnnn = read_word(self.rom, offset + 2) as usize;
+                    self.label(nnnn, Label::Data);
+                    pending.push((next, Some(nnnn)));
+                }
+                Instruction::Draw(_, _, n) => {
+                    if let Some(index) = index {
+                        self.mark_sprite(index, if n == 0 { 32 } else { n as usize });
+                    }
+                    pending.push((next, index));
+                }
+                Instruction::AddIndex(_)
+                | Instruction::Font(_)
+                | Instruction::BigFont(_)
+                | Instruction::Store(_)
+                | Instruction::Load(_) => pending.push((next, None)),
+                _ => pending.push((next, index)),
+            }
+        }
+    }
+
+    // Offset into the ROM of the `len` bytes at `address`, if they are all
+    // part of the ROM.
+    fn offset(&self, address: usize, len: usize) -> Option<usize> {
+        let offset = address.checked_sub(PROGRAM_START)?;
+        if offset + len <= self.rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn label(&mut self, address: usize, label: Label) {
+        if self.offset(address, 1).is_some() {
+            let entry = self.labels.entry(address).or_insert(label);
+            *entry = (*entry).max(label);
+        }
+    }
+
+    fn mark_sprite(&mut self, address: usize, len: usize) {
+        for address in address..address + len {
+            if let Some(offset) = self.offset(address, 1) {
+                if self.bytes[offset] == Byte::Unknown {
+                    self.bytes[offset] = Byte::Sprite;
+                }
+            }
+        }
+    }
+
+    // The label at `address`, unless it points into the middle of an
+    // instruction where it cannot be placed.
+    fn label_name(&self, address: usize) -> Option<String> {
+        let label = self.labels.get(&address)?;
+        if self.bytes[address - PROGRAM_START] == Byte::Operand {
+            return None;
+        }
+        Some(match label {
+            Label::Main => "main".to_string(),
+            Label::Sub => format!("sub_{:03X}", address),
+            Label::Code => format!("label_{:03X}", address),
+            Label::Data => format!("data_{:03X}", address),
+        })
+    }
+
+    // The instruction at `offset` with addresses replaced by labels.
+    fn mnemonic(&self, offset: usize) -> String {
+        let target = |address: u16| {
+            self.label_name(address as usize)
+                .unwrap_or_else(|| format!("0x{:03X}", address))
+        };
+        match decode(read_word(self.rom, offset)) {
+            Instruction::Jump(nnn) => format!("jump {}", target(nnn)),
+            Instruction::Call(nnn) => match self.label_name(nnn as usize) {
+                Some(name) => name,
+                None => format!(":call 0x{:03X}", nnn),
+            },
+            Instruction::JumpOffset(nnn) => format!("jump0 {}", target(nnn)),
+            Instruction::SetIndex(nnn) => format!("i := {}", target(nnn)),
+            Instruction::LongIndex => {
+                let nnnn = read_word(self.rom, offset + 2);
+                let name = self.label_name(nnnn as usize);
+                format!(
+                    "i := long {}",
+                    name.unwrap_or_else(|| format!("0x{:04X}", nnnn))
+                )
+            }
+            instruction => instruction.to_string(),
+        }
+    }
+}
+
+fn read_word(rom: &[u8], offset: usize) -> u16 {
+    (rom[offset] as u16) << 8 | rom[offset + 1] as u16
+}
@@ -0,0 +1,281 @@
+use crate::error::StateError;
+use crate::state::{StateReader, StateWriter};
+use std::str::FromStr;
+
+pub const SCREEN_WIDTH: usize = 128;
+pub const SCREEN_HEIGHT: usize = 64;
+pub const PLANE_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Low,  // 64x32, each pixel drawn as 2x2 physical pixels (SCHIP lores)
+    High, // 128x64 (SCHIP hires)
+    Tall, // 64x64 (HiRes CHIP-8)
+}
+
+impl Resolution {
+    // Size in pixels as addressed by DXYN.
+    pub fn size(self) -> (usize, usize) {
+        match self {
+            Resolution::Low => (64, 32),
+            Resolution::High => (128, 64),
+            Resolution::Tall => (64, 64),
+        }
+    }
+
+    fn scale(self) -> usize {
+        match self {
+            Resolution::Low => 2,
+            Resolution::High | Resolution::Tall => 1,
+        }
+    }
+}
+
+// Screen made of up to PLANE_COUNT bitplanes. The color of a pixel is the
+// combination of its plane bits, plane 0 being the least significant.
+//
+// Pixels are stored at the physical resolution, which is 128x64 in lores and
+// hires mode so that lores scrolling moves by half pixels like on SCHIP, and
+// 64x64 in HiRes CHIP-8 mode. pixel(), width() and height() use physical
+// pixels while toggle() uses the pixels of the active resolution.
+#[derive(Clone)]
+pub struct Framebuffer {
+    planes: [[u8; SCREEN_WIDTH * SCREEN_HEIGHT]; PLANE_COUNT],
+    resolution: Resolution,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            planes: [[0; SCREEN_WIDTH * SCREEN_HEIGHT]; PLANE_COUNT],
+            resolution: Resolution::Low,
+        }
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    // Switch the resolution. The physical pixels are kept, except when
+    // entering or leaving HiRes CHIP-8 mode, which clears the screen.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        if (resolution == Resolution::Tall) != (self.resolution == Resolution::Tall) {
+            self.clear(0xFF);
+        }
+        self.resolution = resolution;
+    }
+
+    pub fn width(&self) -> usize {
+        let (width, _) = self.resolution.size();
+        width * self.resolution.scale()
+    }
+
+    pub fn height(&self) -> usize {
+        let (_, height) = self.resolution.size();
+        height * self.resolution.scale()
+    }
+
+    // Color index of the physical pixel at (x, y), 0 to 2^PLANE_COUNT - 1.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.planes
+            .iter()
+            .enumerate()
+            .fold(0, |color, (plane, pixels)| {
+                color | pixels[x + y * SCREEN_WIDTH] << plane
+            })
+    }
+
+    // Raw 0/1 physical pixels of one plane, rows SCREEN_WIDTH apart.
+    pub fn plane(&self, plane: usize) -> &[u8] {
+        &self.planes[plane]
+    }
+
+    // XOR the pixel at (x, y) of the active resolution in `plane` and return
+    // whether it was set before.
+    pub fn toggle(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        let scale = self.resolution.scale();
+        let mut was_set = false;
+        for py in y * scale..(y + 1) * scale {
+            for px in x * scale..(x + 1) * scale {
+                let pixel = &mut self.planes[plane][px + py * SCREEN_WIDTH];
+                was_set |= *pixel == 1;
+                *pixel ^= 1;
+            }
+        }
+        was_set
+    }
+
+    // The scrolling and clearing operations apply to the planes in the
+    // bitmask `planes` and move by `n` physical pixels.
+
+    pub fn clear(&mut self, planes: u8) {
+        for pixels in self.selected(planes) {
+            *pixels = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        }
+    }
+
+    pub fn scroll_down(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(height);
+        for pixels in self.selected(planes) {
+            for y in (0..height).rev() {
+                let row = y * SCREEN_WIDTH;
+                if y >= n {
+                    pixels.copy_within(row - n * SCREEN_WIDTH..row - n * SCREEN_WIDTH + width, row);
+                } else {
+                    pixels[row..row + width].iter_mut().for_each(|p| *p = 0);
+                }
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(height);
+        for pixels in self.selected(planes) {
+            for y in 0..height {
+                let row = y * SCREEN_WIDTH;
+                if y + n < height {
+                    pixels.copy_within(row + n * SCREEN_WIDTH..row + n * SCREEN_WIDTH + width, row);
+                } else {
+                    pixels[row..row + width].iter_mut().for_each(|p| *p = 0);
+                }
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(width);
+        for pixels in self.selected(planes) {
+            for row in pixels.chunks_mut(SCREEN_WIDTH).take(height) {
+                row.copy_within(..width - n, n);
+                row[..n].iter_mut().for_each(|p| *p = 0);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, planes: u8, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(width);
+        for pixels in self.selected(planes) {
+            for row in pixels.chunks_mut(SCREEN_WIDTH).take(height) {
+                row.copy_within(n..width, 0);
+                row[width - n..width].iter_mut().for_each(|p| *p = 0);
+            }
+        }
+    }
+
+    fn selected(
+        &mut self,
+        planes: u8,
+    ) -> impl Iterator<Item = &mut [u8; SCREEN_WIDTH * SCREEN_HEIGHT]> {
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(plane, _)| planes & (1 << plane) != 0)
+            .map(|(_, pixels)| pixels)
+    }
+
+    // Append the resolution and the pixels packed 8 to a byte.
+    pub(crate) fn write_state(&self, writer: &mut StateWriter) {
+        writer.u8(match self.resolution {
+            Resolution::Low => 0,
+            Resolution::High => 1,
+            Resolution::Tall => 2,
+        });
+        for pixels in &self.planes {
+            for chunk in pixels.chunks(8) {
+                writer.u8(chunk.iter().fold(0, |byte, &pixel| byte << 1 | pixel));
+            }
+        }
+    }
+
+    pub(crate) fn read_state(reader: &mut StateReader) -> Result<Framebuffer, StateError> {
+        let resolution = match reader.u8()? {
+            0 => Resolution::Low,
+            1 => Resolution::High,
+            2 => Resolution::Tall,
+            _ => return Err(StateError::Invalid("resolution")),
+        };
+        let mut framebuffer = Framebuffer {
+            resolution,
+            ..Framebuffer::new()
+        };
+        for pixels in &mut framebuffer.planes {
+            let packed = reader.bytes(pixels.len() / 8)?;
+            for (pixel_index, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = packed[pixel_index / 8] >> (7 - pixel_index % 8) & 1;
+            }
+        }
+        Ok(framebuffer)
+    }
+}
+
+// Colors for the pixel values of a Framebuffer. Color indexes beyond the
+// number of entries wrap around, so a 4-entry palette covers two planes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        self.colors[index as usize % self.colors.len()]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: vec![
+                [0x1A, 0x33, 0x4D], // Background
+                [0xE6, 0xE6, 0xE6], // Plane 0
+                [0xFF, 0x66, 0x00], // Plane 1
+                [0x66, 0x22, 0x00], // Planes 0 and 1
+                [0x2E, 0x8B, 0x57],
+                [0x98, 0xFB, 0x98],
+                [0xFF, 0xD7, 0x00],
+                [0xB8, 0x86, 0x0B],
+                [0x46, 0x82, 0xB4],
+                [0x87, 0xCE, 0xEB],
+                [0x8B, 0x00, 0x8B],
+                [0xDA, 0x70, 0xD6],
+                [0xB2, 0x22, 0x22],
+                [0xFA, 0x80, 0x72],
+                [0x69, 0x69, 0x69],
+                [0x00, 0x00, 0x00],
+            ],
+        }
+    }
+}
+
+// Parses a comma separated list of 4 or 16 RRGGBB hex colors.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let colors = list
+            .split(',')
+            .map(|hex| {
+                let hex = hex.trim().trim_start_matches('#');
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) if hex.len() == 6 => {
+                        Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+                    }
+                    _ => Err(format!("invalid color: {}", hex)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match colors.len() {
+            4 | 16 => Ok(Palette { colors }),
+            n => Err(format!("palette needs 4 or 16 colors, got {}", n)),
+        }
+    }
+}
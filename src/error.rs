@@ -0,0 +1,232 @@
+use crate::quirks::Quirks;
+use std::str::FromStr;
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    RomTooLarge { size: usize, max: usize },
+    File { path: String, source: io::Error },
+    UnknownOpcode { opcode: u16, address: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    MemoryOutOfBounds { address: usize, pc: u16 },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, the maximum is {}", size, max)
+            }
+            EmulatorError::File { path, source } => write!(f, "cannot read {}: {}", path, source),
+            EmulatorError::UnknownOpcode { opcode, address } => {
+                write!(f, "unknown opcode {:#06x} at {:#06x}", opcode, address)
+            }
+            EmulatorError::StackOverflow { address } => {
+                write!(f, "stack overflow at {:#06x}", address)
+            }
+            EmulatorError::StackUnderflow { address } => {
+                write!(f, "return with empty stack at {:#06x}", address)
+            }
+            EmulatorError::MemoryOutOfBounds { address, pc } => write!(
+                f,
+                "memory access out of bounds at {:#06x} by instruction at {:#06x}",
+                address, pc
+            ),
+        }
+    }
+}
+
+impl error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            EmulatorError::File { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// An error in assembly source and the line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {}
+
+// An error in a keymap file and the line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for KeymapError {}
+
+// A reference trace that cannot be read or parsed.
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(err) => write!(f, "cannot read trace: {}", err),
+            TraceError::Syntax { line, message } => write!(f, "trace line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for TraceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TraceError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::Io(err)
+    }
+}
+
+// A movie file that cannot be read or parsed.
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Syntax { line: usize, message: String },
+    Quirks(Quirks), // The quirks the movie was recorded with
+    Start,          // The ROM or the loaded save state differ
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "cannot read movie: {}", err),
+            MovieError::Syntax { line, message } => write!(f, "movie line {}: {}", line, message),
+            MovieError::Quirks(quirks) => {
+                write!(f, "the movie was recorded with --quirks {}", quirks)
+            }
+            MovieError::Start => write!(
+                f,
+                "the movie was recorded from a different ROM or --load-state"
+            ),
+        }
+    }
+}
+
+impl error::Error for MovieError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MovieError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+// Data that is not a save state this version can load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    NotAState,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str), // The named value is out of range
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+// What the CPU does when an instruction fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorAction {
+    Halt, // Stop execution and return the error from step()
+    Skip, // Silently continue with the next instruction
+    Log,  // Print the error to stderr and continue with the next instruction
+}
+
+impl FromStr for ErrorAction {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "halt" => Ok(ErrorAction::Halt),
+            "skip" => Ok(ErrorAction::Skip),
+            "log" => Ok(ErrorAction::Log),
+            _ => Err(format!("unknown error action: {}", name)),
+        }
+    }
+}
+
+// The action taken for each kind of execution error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorPolicy {
+    pub unknown_opcode: ErrorAction,
+    pub stack: ErrorAction,  // Stack overflow and underflow
+    pub memory: ErrorAction, // Out-of-bounds memory access
+}
+
+impl ErrorPolicy {
+    pub fn all(action: ErrorAction) -> ErrorPolicy {
+        ErrorPolicy {
+            unknown_opcode: action,
+            stack: action,
+            memory: action,
+        }
+    }
+
+    pub fn action(&self, error: &EmulatorError) -> ErrorAction {
+        match error {
+            EmulatorError::UnknownOpcode { .. } => self.unknown_opcode,
+            EmulatorError::StackOverflow { .. } | EmulatorError::StackUnderflow { .. } => {
+                self.stack
+            }
+            EmulatorError::MemoryOutOfBounds { .. } => self.memory,
+            EmulatorError::RomTooLarge { .. } | EmulatorError::File { .. } => ErrorAction::Halt,
+        }
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy {
+            unknown_opcode: ErrorAction::Log,
+            stack: ErrorAction::Halt,
+            memory: ErrorAction::Halt,
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use crate::Options;
+use chip8::{
+    AudioPattern, AudioSink, Buzzer, Keymap, Movie, Palette, Rewind, CPU, TIMER_FREQUENCY,
+};
+use ggez::audio::{self, SoundSource};
+use ggez::event::{self, Axis, Button, GamepadId, KeyCode, KeyMods};
+use ggez::graphics;
+use ggez::input;
+use ggez::timer;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+const QUIT_KEY: KeyCode = KeyCode::Escape;
+const MUTE_KEY: KeyCode = KeyCode::M;
+// Load slot 1 to 4, or save with Shift held
+const SLOT_KEYS: [KeyCode; 4] = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+const REWIND_KEY: KeyCode = KeyCode::Back; // Run backwards while held
+const REWIND_INTERVAL: u32 = 30; // Frames between rewind snapshots
+const STICK_THRESHOLD: f32 = 0.5; // How far a stick must be pushed to press a direction
+
+// The lowercase names of all KeyCode variants in winit 0.19.5, the version
+// ggez 0.5.1 uses, as key_name formats them. Compare it with the KeyCode
+// enum when upgrading ggez.
+const KEY_NAMES: &str =
+    "key1 key2 key3 key4 key5 key6 key7 key8 key9 key0 a b c d e f g h i j k l m n o p q r \
+    s t u v w x y z escape f1 f2 f3 f4 f5 f6 f7 f8 f9 f10 f11 f12 f13 f14 f15 f16 f17 f18 \
+    f19 f20 f21 f22 f23 f24 snapshot scroll pause insert home delete end pagedown pageup \
+    left up right down back return space compose caret numlock numpad0 numpad1 numpad2 \
+    numpad3 numpad4 numpad5 numpad6 numpad7 numpad8 numpad9 abntc1 abntc2 add apostrophe \
+    apps at ax backslash calculator capital colon comma convert decimal divide equals \
+    grave kana kanji lalt lbracket lcontrol lshift lwin mail mediaselect mediastop minus \
+    multiply mute mycomputer navigateforward navigatebackward nexttrack noconvert \
+    numpadcomma numpadenter numpadequals oem102 period playpause power prevtrack ralt \
+    rbracket rcontrol rshift rwin semicolon slash sleep stop subtract sysrq tab underline \
+    unlabeled volumedown volumeup wake webback webfavorites webforward webhome webrefresh \
+    websearch webstop yen copy paste cut";
+// The names button_name gives all Buttons but Unknown
+const PAD_BUTTONS: &str = "pad-south pad-east pad-north pad-west pad-c pad-z \
+    pad-lefttrigger pad-lefttrigger2 pad-righttrigger pad-righttrigger2 pad-select pad-start \
+    pad-mode pad-leftthumb pad-rightthumb pad-dpadup pad-dpaddown pad-dpadleft pad-dpadright";
+const PAD_AXES: [Axis; 6] = [
+    Axis::LeftStickX,
+    Axis::LeftStickY,
+    Axis::RightStickX,
+    Axis::RightStickY,
+    Axis::DPadX,
+    Axis::DPadY,
+];
+
+struct Emulator {
+    cpu: CPU,
+    instructions_per_frame: u32,
+    buzzer: Buzzer,
+    speaker: Option<Speaker>,
+    palette: Palette,
+    rom: String,
+    rewind: Rewind,
+    recording: Option<Movie>,
+    replay: Vec<[bool; 16]>, // Keys of the replayed movie, live input after it ends
+    frame: usize,
+    keymap: Keymap,
+    pad: HashSet<PadInput>, // Pressed inputs of all gamepads
+}
+
+// Where a gamepad input comes from. A D-pad may report the same direction
+// as both a button and an axis, and each is released on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum PadSource {
+    Button,
+    Axis,
+}
+
+// A pressed gamepad input: the pad, the source and the keymap name. A name
+// is held while any pad and source holds it.
+type PadInput = (GamepadId, PadSource, String);
+
+impl Emulator {
+    // Save states are stored next to the ROM as ROM.state1 to ROM.state4.
+    fn slot_path(&self, slot: usize) -> String {
+        format!("{}.state{}", self.rom, slot)
+    }
+
+    fn save_slot(&self, slot: usize) {
+        let path = self.slot_path(slot);
+        match fs::write(&path, self.cpu.save_state()) {
+            Ok(()) => println!("saved state to {}", path),
+            Err(err) => eprintln!("cannot save state to {}: {}", path, err),
+        }
+    }
+
+    // Jumping to another state would make a recorded or replayed movie
+    // disagree with the run.
+    fn follows_movie(&self) -> bool {
+        self.recording.is_some() || self.frame < self.replay.len()
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        if self.follows_movie() {
+            eprintln!("cannot load states while recording or replaying a movie");
+            return;
+        }
+        let path = self.slot_path(slot);
+        let result = fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|state| self.cpu.load_state(&state).map_err(|err| err.to_string()));
+        match result {
+            Ok(()) => {
+                // The history leads up to the state before the load
+                self.rewind.clear();
+                println!("loaded state from {}", path)
+            }
+            Err(err) => eprintln!("cannot load state from {}: {}", path, err),
+        }
+    }
+}
+
+// Plays the buzzer through ggez by looping one second of the rendered tone
+// and pausing it while the sound timer is inactive. The tone is rebuilt when
+// the program loads a new XO-CHIP audio pattern.
+struct Speaker {
+    source: audio::Source,
+    pattern: Option<AudioPattern>,
+}
+
+impl Speaker {
+    fn new(ctx: &mut ggez::Context, buzzer: &Buzzer) -> ggez::GameResult<Speaker> {
+        let mut tone_buzzer = buzzer.clone();
+        tone_buzzer.muted = false;
+        let mut tone = vec![0.0; tone_buzzer.sample_rate() as usize];
+        tone_buzzer.render(true, &mut tone);
+
+        let mut bytes = Vec::new();
+        chip8::write_wav(&mut bytes, &tone, tone_buzzer.sample_rate())?;
+        let mut source = audio::Source::from_data(ctx, audio::SoundData::from_bytes(&bytes))?;
+        source.set_repeat(true);
+        Ok(Speaker {
+            source,
+            pattern: buzzer.pattern(),
+        })
+    }
+}
+
+impl AudioSink for Speaker {
+    fn frame(&mut self, buzzer: &mut Buzzer, active: bool) {
+        let sounding = active && !buzzer.muted;
+        if sounding && !self.source.playing() {
+            if self.source.paused() {
+                self.source.resume();
+            } else if let Err(err) = self.source.play() {
+                eprintln!("audio error: {}", err);
+            }
+        } else if !sounding && self.source.playing() {
+            self.source.pause();
+        }
+    }
+}
+
+// The CHIP-8 keys whose host keys or gamepad inputs in `pad` are down.
+fn read_keys(ctx: &ggez::Context, keymap: &Keymap, pad: &HashSet<PadInput>) -> [bool; 16] {
+    let pressed: Vec<String> = input::keyboard::pressed_keys(ctx)
+        .iter()
+        .map(|&key| key_name(key))
+        .collect();
+    keymap.keys(|name| {
+        pad.iter().any(|(_, _, input)| input == name) || pressed.iter().any(|key| key == name)
+    })
+}
+
+fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key).to_ascii_lowercase()
+}
+
+// Whether a keymap may bind `name`: a name key_name, button_name or
+// axis_names can return, but not a key the emulator itself uses.
+pub fn is_known_key(name: &str) -> bool {
+    let reserved = [QUIT_KEY, MUTE_KEY, REWIND_KEY]
+        .iter()
+        .chain(&SLOT_KEYS)
+        .any(|&key| key_name(key) == name);
+    if reserved {
+        return false;
+    }
+    let mut names = KEY_NAMES
+        .split_whitespace()
+        .chain(PAD_BUTTONS.split_whitespace());
+    names.any(|key| key == name)
+        || PAD_AXES
+            .iter()
+            .filter_map(|&axis| axis_names(axis))
+            .any(|(negative, positive)| name == negative || name == positive)
+}
+
+fn button_name(button: Button) -> String {
+    format!("pad-{:?}", button).to_ascii_lowercase()
+}
+
+// The names of the negative and positive direction of `axis`. Y axes point up.
+fn axis_names(axis: Axis) -> Option<(&'static str, &'static str)> {
+    match axis {
+        Axis::LeftStickX => Some(("pad-leftstickleft", "pad-leftstickright")),
+        Axis::LeftStickY => Some(("pad-leftstickdown", "pad-leftstickup")),
+        Axis::RightStickX => Some(("pad-rightstickleft", "pad-rightstickright")),
+        Axis::RightStickY => Some(("pad-rightstickdown", "pad-rightstickup")),
+        // Some gamepads report the D-pad as axes instead of buttons
+        Axis::DPadX => Some(("pad-dpadleft", "pad-dpadright")),
+        Axis::DPadY => Some(("pad-dpaddown", "pad-dpadup")),
+        _ => None,
+    }
+}
+
+impl event::EventHandler for Emulator {
+    fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+        while timer::check_update_time(ctx, TIMER_FREQUENCY) {
+            let rewinding =
+                input::keyboard::is_key_pressed(ctx, REWIND_KEY) && !self.follows_movie();
+            if rewinding {
+                self.rewind
+                    .rewind(&mut self.cpu, self.instructions_per_frame);
+            } else {
+                let keys = match self.replay.get(self.frame) {
+                    Some(&keys) => keys,
+                    None => read_keys(ctx, &self.keymap, &self.pad),
+                };
+                if let Some(movie) = &mut self.recording {
+                    movie.frames.push(keys);
+                }
+                self.frame += 1;
+                self.rewind.record(&self.cpu, keys);
+                self.cpu.set_keys(keys);
+                if let Err(err) = self.cpu.run_frame(self.instructions_per_frame) {
+                    eprintln!("error: {}", err);
+                }
+            }
+            self.buzzer.set_pattern(self.cpu.audio_pattern());
+            if let Some(speaker) = &mut self.speaker {
+                if speaker.pattern != self.buzzer.pattern() {
+                    speaker.source.stop();
+                    *speaker = Speaker::new(ctx, &self.buzzer)?;
+                }
+                speaker.frame(&mut self.buzzer, self.cpu.is_beeping() && !rewinding);
+            }
+        }
+        if self.cpu.is_halted() {
+            event::quit(ctx);
+        }
+        Ok(())
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut ggez::Context,
+        keycode: KeyCode,
+        keymods: KeyMods,
+        repeat: bool,
+    ) {
+        match keycode {
+            QUIT_KEY => event::quit(ctx),
+            MUTE_KEY if !repeat => self.buzzer.muted = !self.buzzer.muted,
+            _ if !repeat => {
+                if let Some(index) = SLOT_KEYS.iter().position(|&key| key == keycode) {
+                    if keymods.contains(KeyMods::SHIFT) {
+                        self.save_slot(index + 1);
+                    } else {
+                        self.load_slot(index + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, _ctx: &mut ggez::Context, btn: Button, id: GamepadId) {
+        self.pad.insert((id, PadSource::Button, button_name(btn)));
+    }
+
+    fn gamepad_button_up_event(&mut self, _ctx: &mut ggez::Context, btn: Button, id: GamepadId) {
+        self.pad.remove(&(id, PadSource::Button, button_name(btn)));
+    }
+
+    fn gamepad_axis_event(
+        &mut self,
+        _ctx: &mut ggez::Context,
+        axis: Axis,
+        value: f32,
+        id: GamepadId,
+    ) {
+        if let Some((negative, positive)) = axis_names(axis) {
+            for (name, pressed) in [
+                (negative, value <= -STICK_THRESHOLD),
+                (positive, value >= STICK_THRESHOLD),
+            ] {
+                let input = (id, PadSource::Axis, name.to_string());
+                if pressed {
+                    self.pad.insert(input);
+                } else {
+                    self.pad.remove(&input);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+        if self.cpu.take_draw_flag() {
+            let [r, g, b] = self.palette.color(0);
+            graphics::clear(ctx, graphics::Color::from_rgb(r, g, b));
+            let size = graphics::drawable_size(ctx);
+            let framebuffer = self.cpu.graphics();
+            let pixel_width = size.0 / framebuffer.width() as f32;
+            let pixel_height = size.1 / framebuffer.height() as f32;
+            let mut mesh = graphics::MeshBuilder::new();
+            let mut empty = true;
+
+            for y in 0..framebuffer.height() {
+                for x in 0..framebuffer.width() {
+                    let pixel = framebuffer.pixel(x, y);
+                    if pixel != 0 {
+                        empty = false;
+                        let rect = graphics::Rect::new(
+                            x as f32 * pixel_width,
+                            y as f32 * pixel_height,
+                            pixel_width,
+                            pixel_height,
+                        );
+                        let [r, g, b] = self.palette.color(pixel);
+                        mesh.rectangle(
+                            graphics::DrawMode::fill(),
+                            rect,
+                            graphics::Color::from_rgb(r, g, b),
+                        );
+                    }
+                }
+            }
+
+            // ggez refuses to build a mesh without vertices
+            if !empty {
+                let mesh = mesh.build(ctx)?;
+                graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
+            }
+            graphics::present(ctx)?;
+        }
+        timer::yield_now();
+        Ok(())
+    }
+}
+
+// Run `cpu` in a window, taking the keys of the first frames from `replay`.
+pub fn run(
+    cpu: CPU,
+    options: Options,
+    replay: Vec<[bool; 16]>,
+    keymap: Keymap,
+) -> ggez::GameResult {
+    let wm = ggez::conf::WindowMode {
+        width: 640.0,
+        height: 320.0,
+        maximized: false,
+        fullscreen_type: ggez::conf::FullscreenType::Windowed,
+        borderless: false,
+        min_width: 0.0,
+        max_width: 0.0,
+        min_height: 0.0,
+        max_height: 0.0,
+        resizable: true,
+    };
+
+    let cb = ggez::ContextBuilder::new("chip8", "haussbrandt").window_mode(wm);
+    let (ctx, event_loop) = &mut cb.build()?;
+
+    let speaker = match Speaker::new(ctx, &options.buzzer) {
+        Ok(speaker) => Some(speaker),
+        Err(err) => {
+            eprintln!("audio disabled: {}", err);
+            None
+        }
+    };
+    let recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&cpu, options.instructions_per_frame));
+    let state = &mut Emulator {
+        cpu,
+        instructions_per_frame: options.instructions_per_frame,
+        buzzer: options.buzzer,
+        speaker,
+        palette: options.palette,
+        rom: options.rom,
+        rewind: Rewind::new(
+            (options.rewind as usize).saturating_mul(TIMER_FREQUENCY as usize),
+            REWIND_INTERVAL,
+        ),
+        recording,
+        replay,
+        frame: 0,
+        keymap,
+        pad: HashSet::new(),
+    };
+    let result = event::run(ctx, event_loop, state);
+    if let (Some(path), Some(movie)) = (&options.record, &state.recording) {
+        let written = File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            movie.write(&mut out)?;
+            out.flush()
+        });
+        match written {
+            Ok(()) => println!(
+                "recorded {} frames to {}",
+                movie.frames.len(),
+                path.display()
+            ),
+            Err(err) => eprintln!("cannot write movie to {}: {}", path.display(), err),
+        }
+    }
+    result
+}
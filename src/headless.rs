@@ -0,0 +1,110 @@
+use chip8::{AudioSink, Buzzer, EmulatorError, Palette, CPU};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub enum Limit {
+    Cycles(u64),
+    Frames(u64),
+}
+
+// Run the interpreter without a window, `instructions_per_frame` instructions
+// and one timer tick per frame, until `limit` is reached or the program exits
+// via 00FD or an error. Frame N runs with the keys in `input[N]`, if any. If
+// `audio` is given, the buzzer output of every frame is fed to the sink.
+pub fn run(
+    cpu: &mut CPU,
+    limit: Limit,
+    instructions_per_frame: u32,
+    input: &[[bool; 16]],
+    mut audio: Option<(&mut Buzzer, &mut dyn AudioSink)>,
+) -> Result<(), EmulatorError> {
+    let mut cycles = 0;
+    let mut frames = 0;
+    while !cpu.is_halted() {
+        match limit {
+            Limit::Cycles(max) if cycles >= max => break,
+            Limit::Frames(max) if frames >= max => break,
+            _ => {}
+        }
+        if let Some(&keys) = input.get(frames as usize) {
+            cpu.set_keys(keys);
+        }
+        for _ in 0..instructions_per_frame {
+            if let Limit::Cycles(max) = limit {
+                if cycles >= max {
+                    break;
+                }
+            }
+            cpu.step()?;
+            cycles += 1;
+        }
+        cpu.vblank();
+        if let Some((buzzer, sink)) = &mut audio {
+            buzzer.set_pattern(cpu.audio_pattern());
+            sink.frame(buzzer, cpu.is_beeping());
+        }
+        frames += 1;
+    }
+    Ok(())
+}
+
+// Write the framebuffer as one line per row: '.' for background, '#' for
+// color 1 and a hex digit for the other colors.
+pub fn write_text<W: Write>(cpu: &CPU, out: &mut W) -> io::Result<()> {
+    let graphics = cpu.graphics();
+    for y in 0..graphics.height() {
+        let line: String = (0..graphics.width())
+            .map(|x| match graphics.pixel(x, y) {
+                0 => '.',
+                1 => '#',
+                color => std::char::from_digit(color as u32, 16).unwrap_or('?'),
+            })
+            .collect();
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Write the framebuffer as a plain PBM image, where 1 is any non-background pixel.
+pub fn write_pbm<W: Write>(cpu: &CPU, out: &mut W) -> io::Result<()> {
+    let graphics = cpu.graphics();
+    writeln!(out, "P1")?;
+    writeln!(out, "{} {}", graphics.width(), graphics.height())?;
+    for y in 0..graphics.height() {
+        let line: Vec<&str> = (0..graphics.width())
+            .map(|x| if graphics.pixel(x, y) != 0 { "1" } else { "0" })
+            .collect();
+        writeln!(out, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+// Write the framebuffer as a plain PPM image in the colors of `palette`.
+pub fn write_ppm<W: Write>(cpu: &CPU, palette: &Palette, out: &mut W) -> io::Result<()> {
+    let graphics = cpu.graphics();
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", graphics.width(), graphics.height())?;
+    writeln!(out, "255")?;
+    for y in 0..graphics.height() {
+        let line: Vec<String> = (0..graphics.width())
+            .map(|x| {
+                let [r, g, b] = palette.color(graphics.pixel(x, y));
+                format!("{} {} {}", r, g, b)
+            })
+            .collect();
+        writeln!(out, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+// Dump the framebuffer to `path`, choosing the format from a .pbm or .ppm
+// extension and text otherwise.
+pub fn dump(cpu: &CPU, palette: &Palette, path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pbm") => write_pbm(cpu, &mut file),
+        Some("ppm") => write_ppm(cpu, palette, &mut file),
+        _ => write_text(cpu, &mut file),
+    }
+}
@@ -0,0 +1,337 @@
+use std::fmt;
+use std::str::FromStr;
+
+// One CHIP-8, SCHIP or XO-CHIP instruction. X and Y are register numbers,
+// NN a byte, NNN a 12-bit address and N a nibble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    ClearScreen,        // 00E0
+    Return,             // 00EE
+    ScrollDown(u8),     // 00CN
+    ScrollUp(u8),       // 00DN (XO-CHIP)
+    ScrollRight,        // 00FB
+    ScrollLeft,         // 00FC
+    Exit,               // 00FD
+    LowRes,             // 00FE
+    HighRes,            // 00FF
+    Jump(u16),          // 1NNN
+    Call(u16),          // 2NNN
+    SkipEqImm(u8, u8),  // 3XNN
+    SkipNeImm(u8, u8),  // 4XNN
+    SkipEqReg(u8, u8),  // 5XY0
+    StoreRange(u8, u8), // 5XY2 (XO-CHIP)
+    LoadRange(u8, u8),  // 5XY3 (XO-CHIP)
+    SetImm(u8, u8),     // 6XNN
+    AddImm(u8, u8),     // 7XNN
+    Set(u8, u8),        // 8XY0
+    Or(u8, u8),         // 8XY1
+    And(u8, u8),        // 8XY2
+    Xor(u8, u8),        // 8XY3
+    Add(u8, u8),        // 8XY4
+    Sub(u8, u8),        // 8XY5
+    ShiftRight(u8, u8), // 8XY6
+    SubReverse(u8, u8), // 8XY7
+    ShiftLeft(u8, u8),  // 8XYE
+    SkipNeReg(u8, u8),  // 9XY0
+    SetIndex(u16),      // ANNN
+    JumpOffset(u16),    // BNNN
+    Random(u8, u8),     // CXNN
+    Draw(u8, u8, u8),   // DXYN
+    SkipKey(u8),        // EX9E
+    SkipNotKey(u8),     // EXA1
+    LongIndex,          // F000 NNNN (XO-CHIP), the address is the next word
+    SelectPlanes(u8),   // FN01 (XO-CHIP)
+    LoadAudio,          // F002 (XO-CHIP)
+    GetDelay(u8),       // FX07
+    WaitKey(u8),        // FX0A
+    SetDelay(u8),       // FX15
+    SetSound(u8),       // FX18
+    AddIndex(u8),       // FX1E
+    Font(u8),           // FX29
+    BigFont(u8),        // FX30
+    SetPitch(u8),       // FX3A (XO-CHIP)
+    Bcd(u8),            // FX33
+    Store(u8),          // FX55
+    Load(u8),           // FX65
+    SaveFlags(u8),      // FX75
+    LoadFlags(u8),      // FX85
+    Unknown(u16),
+}
+
+// Broad groups of instructions, e.g. for filtering traces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionClass {
+    Flow,    // Jumps, calls, returns, skips and exit
+    Math,    // Register arithmetic and random numbers
+    Memory,  // I and loads and stores
+    Display, // Drawing, scrolling, resolution and plane selection
+    Input,   // Key checks and waits
+    Timer,   // Delay timer
+    Sound,   // Sound timer and XO-CHIP audio
+    Unknown,
+}
+
+impl FromStr for InstructionClass {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "flow" => Ok(InstructionClass::Flow),
+            "math" => Ok(InstructionClass::Math),
+            "memory" => Ok(InstructionClass::Memory),
+            "display" => Ok(InstructionClass::Display),
+            "input" => Ok(InstructionClass::Input),
+            "timer" => Ok(InstructionClass::Timer),
+            "sound" => Ok(InstructionClass::Sound),
+            "unknown" => Ok(InstructionClass::Unknown),
+            _ => Err(format!("unknown instruction class: {}", name)),
+        }
+    }
+}
+
+impl Instruction {
+    // Size in bytes including any operand words.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LongIndex => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn class(&self) -> InstructionClass {
+        match self {
+            Instruction::Return
+            | Instruction::Exit
+            | Instruction::Jump(_)
+            | Instruction::Call(_)
+            | Instruction::JumpOffset(_)
+            | Instruction::SkipEqImm(..)
+            | Instruction::SkipNeImm(..)
+            | Instruction::SkipEqReg(..)
+            | Instruction::SkipNeReg(..) => InstructionClass::Flow,
+            Instruction::SetImm(..)
+            | Instruction::AddImm(..)
+            | Instruction::Set(..)
+            | Instruction::Or(..)
+            | Instruction::And(..)
+            | Instruction::Xor(..)
+            | Instruction::Add(..)
+            | Instruction::Sub(..)
+            | Instruction::ShiftRight(..)
+            | Instruction::SubReverse(..)
+            | Instruction::ShiftLeft(..)
+            | Instruction::Random(..) => InstructionClass::Math,
+            Instruction::StoreRange(..)
+            | Instruction::LoadRange(..)
+            | Instruction::SetIndex(_)
+            | Instruction::LongIndex
+            | Instruction::AddIndex(_)
+            | Instruction::Font(_)
+            | Instruction::BigFont(_)
+            | Instruction::Bcd(_)
+            | Instruction::Store(_)
+            | Instruction::Load(_)
+            | Instruction::SaveFlags(_)
+            | Instruction::LoadFlags(_) => InstructionClass::Memory,
+            Instruction::ClearScreen
+            | Instruction::ScrollDown(_)
+            | Instruction::ScrollUp(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::Draw(..)
+            | Instruction::SelectPlanes(_) => InstructionClass::Display,
+            Instruction::SkipKey(_) | Instruction::SkipNotKey(_) | Instruction::WaitKey(_) => {
+                InstructionClass::Input
+            }
+            Instruction::GetDelay(_) | Instruction::SetDelay(_) => InstructionClass::Timer,
+            Instruction::SetSound(_) | Instruction::LoadAudio | Instruction::SetPitch(_) => {
+                InstructionClass::Sound
+            }
+            Instruction::Unknown(_) => InstructionClass::Unknown,
+        }
+    }
+}
+
+// Octo assembly syntax. F000 NNNN prints as `i := long` without the address,
+// which is the following word in memory.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "clear"),
+            Instruction::Return => write!(f, "return"),
+            Instruction::ScrollDown(n) => write!(f, "scroll-down {}", n),
+            Instruction::ScrollUp(n) => write!(f, "scroll-up {}", n),
+            Instruction::ScrollRight => write!(f, "scroll-right"),
+            Instruction::ScrollLeft => write!(f, "scroll-left"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::LowRes => write!(f, "lores"),
+            Instruction::HighRes => write!(f, "hires"),
+            Instruction::Jump(nnn) => write!(f, "jump 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, ":call 0x{:03X}", nnn),
+            Instruction::SkipEqImm(x, nn) => write!(f, "if v{:X} != 0x{:02X} then", x, nn),
+            Instruction::SkipNeImm(x, nn) => write!(f, "if v{:X} == 0x{:02X} then", x, nn),
+            Instruction::SkipEqReg(x, y) => write!(f, "if v{:X} != v{:X} then", x, y),
+            Instruction::StoreRange(x, y) => write!(f, "save v{:X} - v{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "load v{:X} - v{:X}", x, y),
+            Instruction::SetImm(x, nn) => write!(f, "v{:X} := 0x{:02X}", x, nn),
+            Instruction::AddImm(x, nn) => write!(f, "v{:X} += 0x{:02X}", x, nn),
+            Instruction::Set(x, y) => write!(f, "v{:X} := v{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "v{:X} |= v{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "v{:X} &= v{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "v{:X} ^= v{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "v{:X} += v{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "v{:X} -= v{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "v{:X} >>= v{:X}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "v{:X} =- v{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "v{:X} <<= v{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "if v{:X} == v{:X} then", x, y),
+            Instruction::SetIndex(nnn) => write!(f, "i := 0x{:03X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "jump0 0x{:03X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "v{:X} := random 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "sprite v{:X} v{:X} {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "if v{:X} -key then", x),
+            Instruction::SkipNotKey(x) => write!(f, "if v{:X} key then", x),
+            Instruction::LongIndex => write!(f, "i := long"),
+            Instruction::SelectPlanes(n) => write!(f, "plane {}", n),
+            Instruction::LoadAudio => write!(f, "audio"),
+            Instruction::GetDelay(x) => write!(f, "v{:X} := delay", x),
+            Instruction::WaitKey(x) => write!(f, "v{:X} := key", x),
+            Instruction::SetDelay(x) => write!(f, "delay := v{:X}", x),
+            Instruction::SetSound(x) => write!(f, "buzzer := v{:X}", x),
+            Instruction::AddIndex(x) => write!(f, "i += v{:X}", x),
+            Instruction::Font(x) => write!(f, "i := hex v{:X}", x),
+            Instruction::BigFont(x) => write!(f, "i := bighex v{:X}", x),
+            Instruction::SetPitch(x) => write!(f, "pitch := v{:X}", x),
+            Instruction::Bcd(x) => write!(f, "bcd v{:X}", x),
+            Instruction::Store(x) => write!(f, "save v{:X}", x),
+            Instruction::Load(x) => write!(f, "load v{:X}", x),
+            Instruction::SaveFlags(x) => write!(f, "saveflags v{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "loadflags v{:X}", x),
+            Instruction::Unknown(opcode) => {
+                write!(f, "0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF)
+            }
+        }
+    }
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowRes,
+            0x00FF => Instruction::HighRes,
+            0x00C0..=0x00CF => Instruction::ScrollDown(n),
+            0x00D0..=0x00DF => Instruction::ScrollUp(n),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x1000 => Instruction::Jump(nnn),
+        0x2000 => Instruction::Call(nnn),
+        0x3000 => Instruction::SkipEqImm(x, nn),
+        0x4000 => Instruction::SkipNeImm(x, nn),
+        0x5000 => match n {
+            0x0 => Instruction::SkipEqReg(x, y),
+            0x2 => Instruction::StoreRange(x, y),
+            0x3 => Instruction::LoadRange(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x6000 => Instruction::SetImm(x, nn),
+        0x7000 => Instruction::AddImm(x, nn),
+        0x8000 => match n {
+            0x0 => Instruction::Set(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::Add(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::ShiftRight(x, y),
+            0x7 => Instruction::SubReverse(x, y),
+            0xE => Instruction::ShiftLeft(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9000 if n == 0 => Instruction::SkipNeReg(x, y),
+        0xA000 => Instruction::SetIndex(nnn),
+        0xB000 => Instruction::JumpOffset(nnn),
+        0xC000 => Instruction::Random(x, nn),
+        0xD000 => Instruction::Draw(x, y, n),
+        0xE000 => match nn {
+            0x9E => Instruction::SkipKey(x),
+            0xA1 => Instruction::SkipNotKey(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        0xF000 => match nn {
+            0x00 if x == 0 => Instruction::LongIndex,
+            0x01 => Instruction::SelectPlanes(x),
+            0x02 if x == 0 => Instruction::LoadAudio,
+            0x07 => Instruction::GetDelay(x),
+            0x0A => Instruction::WaitKey(x),
+            0x15 => Instruction::SetDelay(x),
+            0x18 => Instruction::SetSound(x),
+            0x1E => Instruction::AddIndex(x),
+            0x29 => Instruction::Font(x),
+            0x30 => Instruction::BigFont(x),
+            0x33 => Instruction::Bcd(x),
+            0x3A => Instruction::SetPitch(x),
+            0x55 => Instruction::Store(x),
+            0x65 => Instruction::Load(x),
+            0x75 => Instruction::SaveFlags(x),
+            0x85 => Instruction::LoadFlags(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Every opcode disassembles to source that assembles back to it. The
+    // address of `i := long` is not part of the opcode, so it gets one.
+    #[test]
+    fn display_assembles_to_the_decoded_opcode() {
+        for opcode in 0..=0xFFFF {
+            let instruction = decode(opcode);
+            let mut expected = opcode.to_be_bytes().to_vec();
+            let source = match instruction {
+                Instruction::LongIndex => {
+                    expected.extend_from_slice(&[0x12, 0x34]);
+                    format!("{} 0x1234", instruction)
+                }
+                _ => instruction.to_string(),
+            };
+            assert_eq!(assemble(&source), Ok(expected), "{:04X} {}", opcode, source);
+        }
+    }
+
+    #[test]
+    fn decodes_xo_chip_instructions() {
+        assert_eq!(decode(0xF000), Instruction::LongIndex);
+        assert_eq!(decode(0xF100), Instruction::Unknown(0xF100));
+        assert_eq!(decode(0xF301), Instruction::SelectPlanes(3));
+        assert_eq!(decode(0xF002), Instruction::LoadAudio);
+        assert_eq!(decode(0x5122), Instruction::StoreRange(1, 2));
+        assert_eq!(decode(0x00D4), Instruction::ScrollUp(4));
+        assert_eq!(Instruction::LongIndex.size(), 4);
+        assert_eq!(Instruction::ClearScreen.size(), 2);
+    }
+
+    #[test]
+    fn rejects_unused_variants() {
+        for opcode in [0x0000, 0x00E1, 0x5121, 0x8128, 0x9121, 0xE19F, 0xF0FF] {
+            assert_eq!(decode(opcode), Instruction::Unknown(opcode));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::error::KeymapError;
+
+// The host keys bound to each CHIP-8 key, by name. Names are the lowercase
+// names of the keys in the window library, e.g. `q`, `key1`, `up`, `space`
+// or `numpad5`; a single digit is short for the number key, so `1` means
+// `key1`. Gamepad buttons are `pad-` and the lowercase button name, e.g.
+// `pad-south` or `pad-dpadup`, and stick directions `pad-leftstickup`,
+// `pad-rightstickleft` and so on. Keys the emulator itself uses, like
+// Escape or the save slot keys, cannot be bound.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 16],
+}
+
+// The hex keypad on the left of a QWERTY keyboard:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const DEFAULT_LAYOUT: [&str; 16] = [
+    "x", "1", "2", "3", "q", "w", "e", "a", "s", "d", "z", "c", "4", "r", "f", "v",
+];
+
+// The D-pad and left stick move on 2/4/6/8, the bottom face button is 5.
+const DEFAULT_PAD_LAYOUT: [(u8, &str); 9] = [
+    (0x2, "pad-dpadup"),
+    (0x2, "pad-leftstickup"),
+    (0x4, "pad-dpadleft"),
+    (0x4, "pad-leftstickleft"),
+    (0x6, "pad-dpadright"),
+    (0x6, "pad-leftstickright"),
+    (0x8, "pad-dpaddown"),
+    (0x8, "pad-leftstickdown"),
+    (0x5, "pad-south"),
+];
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            keys: DEFAULT_LAYOUT.map(|name| vec![normalize(name)]),
+        };
+        for &(key, name) in &DEFAULT_PAD_LAYOUT {
+            keymap.keys[key as usize].push(name.to_string());
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    // Parse a keymap file. Each line binds a CHIP-8 key to any number of
+    // host keys and gamepad inputs, replacing all default bindings of that
+    // key:
+    //
+    //   # CHIP-8 key = host keys
+    //   5 = w up pad-south
+    //   8 = s down pad-dpaddown
+    //
+    // Lines after a `[NAME]` header only apply to the ROM with the file name
+    // NAME and take precedence over the lines before the first header.
+    // Empty lines and lines starting with `#` are ignored, and every host key
+    // must pass `is_known`, which gets the normalized name.
+    pub fn parse(
+        text: &str,
+        rom: &str,
+        is_known: impl Fn(&str) -> bool,
+    ) -> Result<Keymap, KeymapError> {
+        let mut general = Vec::new();
+        let mut specific = Vec::new();
+        let mut section = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: String| KeymapError {
+                line: number + 1,
+                message,
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| error(format!("expected [ROM], found {}", line)))?;
+                section = Some(name.trim().to_string());
+                continue;
+            }
+            let (key, hosts) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected KEY = HOST KEYS, found {}", line)))?;
+            let key = match u8::from_str_radix(key.trim(), 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(error(format!("invalid CHIP-8 key: {}", key.trim()))),
+            };
+            let hosts: Vec<String> = hosts.split_whitespace().map(normalize).collect();
+            if let Some(host) = hosts.iter().find(|host| !is_known(host)) {
+                return Err(error(format!("unknown or reserved host key: {}", host)));
+            }
+            match &section {
+                None => general.push((key, hosts)),
+                Some(name) if name == rom => specific.push((key, hosts)),
+                Some(_) => {}
+            }
+        }
+
+        let mut keymap = Keymap::default();
+        for (key, hosts) in general.into_iter().chain(specific) {
+            keymap.keys[key as usize] = hosts;
+        }
+        Ok(keymap)
+    }
+
+    // The host keys bound to the CHIP-8 `key`.
+    pub fn bindings(&self, key: u8) -> &[String] {
+        &self.keys[key as usize]
+    }
+
+    // The state of the 16 keys given which host keys are down. A CHIP-8 key
+    // is pressed while any of its host keys is.
+    pub fn keys(&self, is_pressed: impl Fn(&str) -> bool) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (pressed, hosts) in keys.iter_mut().zip(&self.keys) {
+            *pressed = hosts.iter().any(|host| is_pressed(host));
+        }
+        keys
+    }
+}
+
+fn normalize(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    if name.len() == 1 && name.as_bytes()[0].is_ascii_digit() {
+        format!("key{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, rom: &str) -> Result<Keymap, KeymapError> {
+        Keymap::parse(text, rom, |name| name != "escape")
+    }
+
+    #[test]
+    fn rom_sections_take_precedence() {
+        let text = "5 = up
+                    6 = down
+                    [pong.ch8]
+                    5 = space
+                    [tetris.ch8]
+                    5 = k
+                    6 = l
+                    [pong.ch8]
+                    7 = j";
+        let pong = parse(text, "pong.ch8").unwrap();
+        assert_eq!(pong.bindings(5), ["space"]);
+        assert_eq!(pong.bindings(6), ["down"]);
+        assert_eq!(pong.bindings(7), ["j"]);
+        let other = parse(text, "brix.ch8").unwrap();
+        assert_eq!(other.bindings(5), ["up"]);
+        assert_eq!(other.bindings(6), ["down"]);
+        assert_eq!(other.bindings(0), Keymap::default().bindings(0));
+    }
+
+    #[test]
+    fn normalizes_digits() {
+        let keymap = parse("1 = 7 Q NumPad7 pad-South", "rom").unwrap();
+        assert_eq!(keymap.bindings(1), ["key7", "q", "numpad7", "pad-south"]);
+        assert_eq!(parse("0 = 10", "rom").unwrap().bindings(0), ["10"]);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let text = "# Keymap\n\n1 = q\n2 = w escape\n";
+        let err = parse(text, "rom").unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.to_string(),
+            "line 4: unknown or reserved host key: escape"
+        );
+        assert_eq!(parse("[rom", "rom").unwrap_err().line, 1);
+        assert_eq!(
+            parse("1 = q\nG = w", "rom").unwrap_err().to_string(),
+            "line 2: invalid CHIP-8 key: G"
+        );
+        assert_eq!(
+            parse("1 q", "rom").unwrap_err().message,
+            "expected KEY = HOST KEYS, found 1 q"
+        );
+    }
+
+    #[test]
+    fn presses_keys_while_any_binding_is_down() {
+        let keymap = parse("5 = w up", "rom").unwrap();
+        let keys = keymap.keys(|name| name == "up");
+        assert!(keys[5]);
+        assert_eq!(keys.iter().filter(|&&pressed| pressed).count(), 1);
+    }
+}
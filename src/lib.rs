@@ -0,0 +1,43 @@
+//! CHIP-8 / SCHIP / XO-CHIP interpreter core without any windowing dependency.
+
+mod asm;
+mod audio;
+mod compare;
+mod cpu;
+mod debugger;
+mod disasm;
+mod display;
+mod error;
+mod instruction;
+mod keymap;
+mod movie;
+mod quirks;
+mod random;
+mod rewind;
+mod state;
+mod trace;
+mod watch;
+mod wav;
+
+pub use asm::assemble;
+pub use audio::{AudioSink, Buzzer, SampleBuffer, Waveform, DEFAULT_SAMPLE_RATE};
+pub use compare::{compare_trace, Difference, Divergence, Field, Mismatch};
+pub use cpu::{
+    AudioPattern, CPU, DEFAULT_INSTRUCTIONS_PER_FRAME, MEMORY_SIZE, PROGRAM_START, TIMER_FREQUENCY,
+};
+pub use debugger::{Debugger, Stop};
+pub use disasm::{write_disassembly, write_listing};
+pub use display::{Framebuffer, Palette, Resolution, PLANE_COUNT, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use error::{
+    AsmError, EmulatorError, ErrorAction, ErrorPolicy, KeymapError, MovieError, StateError,
+    TraceError,
+};
+pub use instruction::{decode, Instruction, InstructionClass};
+pub use keymap::Keymap;
+pub use movie::Movie;
+pub use quirks::{IndexIncrement, Quirks};
+pub use random::RandomAlgorithm;
+pub use rewind::Rewind;
+pub use trace::{write_trace_line, TraceFilter, TraceLog, TraceSink};
+pub use watch::{Access, Comparison, Condition, Operand, Watchpoint};
+pub use wav::write_wav;
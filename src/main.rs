@@ -1,535 +1,441 @@
-use ggez;
-use ggez::event::{self, KeyCode};
-use ggez::graphics;
-use ggez::input;
-use rand;
-use std::{self, env, fs};
+mod debug;
+#[cfg(feature = "gui")]
+mod gui;
+mod headless;
 
-struct CPU {
-    memory: [u8; 4096],
-    v: [u8; 16],
-    i: u16,  // Index register
-    pc: u16, // Program counter
-    graphics: [u8; 64 * 128],
-    delay_timer: u8,
-    sound_timer: u8,
-    stack: [u16; 16],
-    sp: u16, // Stack pointer
-    key: [bool; 16],
-    draw_flag: bool,
-    rpl_user_flags: [u8; 8],
-    is_extended: bool,
-}
+use chip8::{
+    AudioSink, Buzzer, EmulatorError, ErrorAction, ErrorPolicy, Movie, Palette, Quirks,
+    RandomAlgorithm, SampleBuffer, TraceFilter, TraceLog, CPU, DEFAULT_INSTRUCTIONS_PER_FRAME,
+};
+use headless::Limit;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
 
-impl CPU {
-    fn new() -> CPU {
-        let chip8_fontset = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-            0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
-            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
-            0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
-            0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
-            0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
-            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
-            0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
-            0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
-            0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
-            0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
-        ];
+const USAGE: &str = "usage: chip8-emulator [OPTIONS] ROM
+       chip8-emulator disasm [--linear] ROM
+       chip8-emulator asm SOURCE [ROM]
 
-        let mut memory = [0; 4096];
+options:
+    --quirks PROFILE    default, vip, chip48, schip10, schip11 or xochip
+    --on-error ACTION   halt, skip or log on invalid instructions
+    --ipf N             instructions executed per 60 Hz frame
+    --tone HZ           buzzer frequency (default 440)
+    --waveform NAME     square, triangle, sawtooth or sine
+    --volume V          buzzer volume from 0.0 to 1.0
+    --mute              start with the buzzer muted (toggle with M)
+    --keymap FILE       key and gamepad bindings
+                        (default ~/.config/chip8-emulator/keymap if it exists)
+    --rewind SECONDS    how far back Backspace can rewind (default 10, 0 disables)
+    --palette COLORS    4 or 16 comma separated RRGGBB colors for the bitplanes
+    --rng NAME          random number generator: xorshift (default), lcg or vip
+    --seed N            seed of the random number generator (default random)
+    --record FILE       write the generator, seed, quirks and keys of every frame to a
+                        movie FILE (not with --headless, --debug or --compare)
+    --replay FILE       replay the generator, seed and keys of a movie recorded with --record
+                        from the same ROM, --quirks and --load-state; headless runs stop
+                        when the movie ends, in a window live input takes over
+    --load-state FILE   start from a save state written by --save-state or a slot
+    --debug             run in the terminal debugger, paused at the first instruction
+    --trace FILE        write every executed instruction and the registers to FILE
+    --trace-range A..B  trace: only instructions at hex addresses A up to B
+    --trace-ops CLASSES trace: only flow, math, memory, display, input, timer,
+                        sound or unknown instructions (comma separated)
+    --compare FILE      run without a window, comparing the state before every
+                        instruction with a reference trace in the --trace format
+                        and stopping at the first difference
+    --headless          run without a window and dump the final screen
+    --cycles N          headless: stop after N instructions
+    --frames N          headless: stop after N frames (default 600)
+    --dump FILE         headless: write the screen to FILE (.pbm or .ppm for an image)
+    --wav FILE          headless: record the buzzer output to a WAV file
+    --save-state FILE   headless: save the final state to FILE
 
-        for i in 0..180 {
-            memory[i] = chip8_fontset[i];
-        }
+keys:
+    F1-F4               load the state saved in slot 1-4 (ROM.state1 to ROM.state4)
+    Shift+F1-F4         save the state to slot 1-4
+    Backspace           hold to run backwards frame by frame
+    M                   mute or unmute the buzzer
+    Escape              quit";
 
-        CPU {
-            memory,
-            v: [0; 16],
-            i: 0,
-            pc: 0x200,
-            graphics: [0; 64 * 128],
-            delay_timer: 0,
-            sound_timer: 0,
-            stack: [0; 16],
-            sp: 0,
-            key: [false; 16],
-            draw_flag: false,
-            rpl_user_flags: [0; 8],
-            is_extended: false,
-        }
-    }
+enum Command {
+    Run(Box<Options>),
+    Disasm { rom: String, linear: bool },
+    Asm { source: PathBuf, rom: PathBuf },
+}
 
-    fn set_keys(&mut self, ctx: &mut ggez::Context) {
-        self.key = [false; 16];
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Key1) {
-            self.key[0x1] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Key2) {
-            self.key[0x2] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Key3) {
-            self.key[0x3] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Key4) {
-            self.key[0xC] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Q) {
-            self.key[0x4] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::W) {
-            self.key[0x5] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::E) {
-            self.key[0x6] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::R) {
-            self.key[0xD] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::A) {
-            self.key[0x7] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::S) {
-            self.key[0x8] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::D) {
-            self.key[0x9] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::F) {
-            self.key[0xE] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::Z) {
-            self.key[0xA] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::X) {
-            self.key[0x0] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::C) {
-            self.key[0xB] = true;
-        }
-        if input::keyboard::is_key_pressed(ctx, KeyCode::V) {
-            self.key[0xF] = true;
-        }
-    }
+struct Options {
+    rom: String,
+    debug: bool,
+    headless: bool,
+    limit: Limit,
+    instructions_per_frame: u32,
+    dump: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    quirks: Quirks,
+    buzzer: Buzzer,
+    palette: Palette,
+    error_policy: ErrorPolicy,
+    trace: Option<PathBuf>,
+    trace_filter: TraceFilter,
+    compare: Option<PathBuf>,
+    load_state: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    rewind: u32, // Seconds
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    keymap: Option<PathBuf>,
+    random: RandomAlgorithm,
+    seed: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+}
 
-    fn load_game(&mut self, filename: &str) {
-        let game = fs::read(filename).unwrap();
-        for (i, v) in game.iter().enumerate() {
-            self.memory[0x200 + i] = *v;
-        }
-    }
+fn parse_number<T: std::str::FromStr>(value: Option<&String>, option: &str) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a value", option))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", option, value))
+}
 
-    fn emulate_cycle(&mut self, ctx: &mut ggez::Context) {
-        let opcode =
-            (self.memory[self.pc as usize] as u16) << 8 | self.memory[self.pc as usize + 1] as u16;
-        let x = ((opcode & 0x0F00) >> 8) as usize;
-        let y = ((opcode & 0x00F0) >> 4) as usize;
-        // println!("opcode: {:#04x}, pc: {:#04x}", opcode, self.pc);
-        match opcode & 0xF000 {
-            0x0000 => match opcode & 0x00FF {
-                0x00E0 => {
-                    self.graphics = [0; 64 * 128];
-                    self.pc += 2;
-                } // Clear the screen
-                0x00EE => {
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp as usize];
-                    self.pc += 2;
-                } // Return from subroutine
-                0x00FB => {
-                    for y_line in 0..64 {
-                        for x_line in (0..124).rev() {
-                            self.graphics[x_line + y_line * 128] = self.graphics[x_line - 4 + y_line * 128];
-                        }
-                        for x_line in 0..4 {
-                            self.graphics[x_line + y_line * 128] = 0;
-                        }
-                    }
-                    self.pc += 2;
-                } // Scroll right
-                0x00FC => {
-                    for y_line in 0..64 {
-                        for x_line in 0..124 {
-                            self.graphics[x_line + y_line * 128] = self.graphics[x_line + 4 + y_line * 128];
-                        }
-                        for x_line in 124..128 {
-                            self.graphics[x_line + y_line * 128] = 0;
-                        }
-                    }
-                    self.pc += 2;
-                } // Scroll left
-                0x00FD => {
-                    ggez::event::quit(ctx);
-                    self.pc += 2;
-                } // Exit interpreter
-                0x00FE => {
-                    self.is_extended = false;
-                    self.pc += 2;
-                } // Disable extended mode
-                0x00FF => {
-                    self.is_extended = true;
-                    self.pc += 2;
-                } // Enable extended mode
-                _ => match opcode & 0x00F0 {
-                    0x00C0 => {
-                        let n = opcode & 0x000F;
-                        for x_line in 0..128 {
-                            for y_line in (n..64).rev() {
-                                self.graphics[(x_line + y_line * 128) as usize] = self.graphics[(x_line + (y_line - n) * 128) as usize];
-                            }
-                            for y_line in 0..n {
-                                self.graphics[(x_line + y_line * 128) as usize] = 0;
-                            }
-                        }
-                        self.pc += 2;
-                    } // Scroll display N lines down
-                    _ => println!("Unknown opcode: {:#04x}", opcode),
-                }
-            },
-            0x1000 => {
-                self.pc = opcode & 0x0FFF;
-            } // Jump to address NNN
-            0x2000 => {
-                self.stack[self.sp as usize] = self.pc;
-                self.sp += 1;
-                self.pc = opcode & 0x0FFF;
-            } // Call subroutine at NNN
-            0x3000 => {
-                if self.v[x] == (opcode & 0x00FF) as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            } // Skip if VX equal to NN
-            0x4000 => {
-                if self.v[x] != (opcode & 0x00FF) as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            } // Skip if VX not equal to NN
-            0x5000 => {
-                if self.v[x] == self.v[y] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            } // Skip if VX equal to VY
-            0x6000 => {
-                self.v[x] = (opcode & 0x00FF) as u8;
-                self.pc += 2;
-            } // Set VX to NN
-            0x7000 => {
-                if x != 0xF {
-                    let result = self.v[x].overflowing_add((opcode & 0x00FF) as u8);
-                    self.v[x] = result.0;
-                }
-                self.pc += 2;
-            } // Add NN to VX (carry flag not changed)
-            0x8000 => match opcode & 0x000F {
-                0x0 => {
-                    self.v[x] = self.v[y];
-                    self.pc += 2;
-                } // Set VX to VY
-                0x1 => {
-                    self.v[x] |= self.v[y];
-                    self.pc += 2;
-                } // Set VX to VX or VY
-                0x2 => {
-                    self.v[x] &= self.v[y];
-                    self.pc += 2;
-                } // Set VX to VX and VY
-                0x3 => {
-                    self.v[x] ^= self.v[y];
-                    self.pc += 2;
-                } // Set VX to VX xor VY
-                0x4 => {
-                    let result = self.v[x].overflowing_add(self.v[y]);
-                    self.v[x] = result.0;
-                    if result.1 {
-                        self.v[0xF] = 1;
-                    } else {
-                        self.v[0xF] = 0;
-                    }
-                    self.pc += 2;
-                } // Add VY to VX. Set VF to 1 if carry, 0 if not.
-                0x5 => {
-                    let result = self.v[x].overflowing_sub(self.v[y]);
-                    self.v[x] = result.0;
-                    if result.1 {
-                        self.v[0xF] = 0;
-                    } else {
-                        self.v[0xF] = 1;
-                    }
-                    self.pc += 2;
-                } // Subtract VY from VX. Set VF to 0 if borrow, 1 if not.
-                0x6 => {
-                    self.v[0xF] = self.v[x] & 0x1;
-                    self.v[x] >>= 1;
-                    self.pc += 2;
-                } // Store LSB of VX in VF. Shift VX to right by 1.
-                0x7 => {
-                    let result = self.v[y].overflowing_sub(self.v[x]);
-                    self.v[x] = result.0;
-                    if result.1 {
-                        self.v[0xF] = 0;
-                    } else {
-                        self.v[0xF] = 1;
-                    }
-                    self.pc += 2;
-                } // Set VX to VY - VX. Set VF to 0 if borrow, 1 if not.
-                0xE => {
-                    self.v[0xF] = self.v[x] & 0x80;
-                    self.v[x] <<= 1;
-                    self.pc += 2;
-                } // Store MSB of VX in VF. Shift VX to left by 1.
-                _ => println!("Unknown opcode: {:#04x}", opcode),
-            },
-            0x9000 => {
-                if self.v[x] != self.v[y] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            } // Skip if VX not equal to VY
-            0xA000 => {
-                self.i = opcode & 0xFFF;
-                self.pc += 2;
-            } // Set I to address NNN
-            0xB000 => self.pc = (opcode & 0x0FFF) + self.v[0] as u16, // Jump to address NNN + V0
-            0xC000 => {
-                self.v[x] = rand::random::<u8>() & (opcode & 0x00FF) as u8;
-                self.pc += 2;
-            } // Set VX to result of rand() & NN
-            0xD000 => {
-                let pos_x = self.v[x];
-                let pos_y = self.v[y];
-                let height = opcode & 0x000F;
+// A hex address range START..END, END exclusive.
+fn parse_range(value: Option<&String>, option: &str) -> Result<Range<u16>, String> {
+    let value = value.ok_or(format!("{} needs a range", option))?;
+    let invalid = || format!("invalid range for {}: {}", option, value);
+    let (start, end) = value.split_once("..").ok_or_else(invalid)?;
+    let start = u16::from_str_radix(start.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+    let end = u16::from_str_radix(end.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+    Ok(start..end)
+}
 
-                if (height == 0) & self.is_extended {
-                    for y_line in 0..16 {
-                        let pixels: u16 = ((self.memory[(self.i + y_line) as usize] as u16) << 8)
-                            | (self.memory[(self.i + y_line) as usize] as u16);
-                        for x_line in 0..16 {
-                            if (pixels & (0x8000 >> x_line)) != 0 {
-                                if ((pos_x as u16 + x_line as u16) < 128)
-                                    & ((pos_y as u16 + y_line as u16) < 64)
-                                {
-                                    if self.graphics[((pos_x + x_line) as usize
-                                        + ((pos_y as usize + y_line as usize) * 128))
-                                        as usize]
-                                        == 1
-                                    {
-                                        self.v[0xF] = 1;
-                                    }
-                                    self.graphics[((pos_x + x_line) as usize
-                                        + ((pos_y as usize + y_line as usize) * 128))
-                                        as usize] ^= 1
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    for y_line in 0..height {
-                        let pixels = self.memory[(self.i + y_line) as usize];
-                        for x_line in 0..8 {
-                            if (pixels & (0x80 >> x_line)) != 0 {
-                                if ((pos_x as u16 + x_line as u16)
-                                    < (64 * (1 + self.is_extended as u16)))
-                                    & ((pos_y as u16 + y_line)
-                                        < (128 * (1 + self.is_extended as u16)))
-                                {
-                                    if self.graphics[((pos_x + x_line) as usize
-                                        + ((pos_y as usize + y_line as usize) * 128))
-                                        as usize]
-                                        == 1
-                                    {
-                                        self.v[0xF] = 1;
-                                    }
-                                    self.graphics[((pos_x + x_line) as usize
-                                        + ((pos_y as usize + y_line as usize) * 128))
-                                        as usize] ^= 1
-                                }
-                            }
-                        }
-                    }
-                }
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    if args.first().map(String::as_str) == Some("disasm") {
+        return match &args[1..] {
+            [rom] => Ok(Command::Disasm {
+                rom: rom.clone(),
+                linear: false,
+            }),
+            [flag, rom] if flag == "--linear" => Ok(Command::Disasm {
+                rom: rom.clone(),
+                linear: true,
+            }),
+            _ => Err("disasm needs a ROM file".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("asm") {
+        return match &args[1..] {
+            [source] => Ok(Command::Asm {
+                source: PathBuf::from(source),
+                rom: PathBuf::from(source).with_extension("ch8"),
+            }),
+            [source, rom] => Ok(Command::Asm {
+                source: PathBuf::from(source),
+                rom: PathBuf::from(rom),
+            }),
+            _ => Err("asm needs a source file".to_string()),
+        };
+    }
 
-                self.draw_flag = true;
-                self.pc += 2;
-            } // Draw
-            0xE000 => match opcode & 0x00FF {
-                0x9E => {
-                    if self.key[self.v[x] as usize] {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
-                    }
-                } // Skip if key in VX is pressed
-                0xA1 => {
-                    if !self.key[self.v[x] as usize] {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
-                    }
-                } // Skip if key in VX is not pressed
-                _ => println!("Unknown opcode: {:#04x}", opcode),
-            },
-            0xF000 => match opcode & 0x00FF {
-                0x07 => {
-                    self.v[x] = self.delay_timer;
-                    self.pc += 2;
-                } // Set VX to delay timer
-                0x0A => {
-                    for key in self.key.iter().enumerate() {
-                        if *key.1 {
-                            self.v[x] = key.0 as u8;
-                            self.pc += 2;
-                            break;
-                        }
-                    }
-                } // Wait for press key, store in VX
-                0x15 => {
-                    self.delay_timer = self.v[x];
-                    self.pc += 2;
-                } // Set delay timer to VX
-                0x18 => {
-                    self.sound_timer = self.v[x];
-                    self.pc += 2;
-                } // Set sound timer to VX
-                0x1E => {
-                    if x != 0xF {
-                        self.i += self.v[x] as u16;
-                    }
-                    self.pc += 2;
-                } // Add VX to I if X is not F
-                0x29 => {
-                    self.i = self.v[x] as u16 * 5;
-                    self.pc += 2;
-                } // Set I to the location of the sprite for the 5-byte character in VX.
-                0x30 => {
-                    self.i = self.v[x] as u16 * 10 + 80;
-                    self.pc += 2;
-                } // Set I to the location of the sprite for the 10-byte character in VX.
-                0x33 => {
-                    self.memory[self.i as usize] = self.v[x] / 100;
-                    self.memory[self.i as usize + 1] = (self.v[x] / 10) % 10;
-                    self.memory[self.i as usize + 2] = (self.v[x] % 100) % 10;
-                    self.pc += 2;
-                } // Store BCD representation of VX at the address in I
-                0x55 => {
-                    for j in 0..=x {
-                        self.memory[self.i as usize + j] = self.v[j];
-                    }
-                    self.pc += 2;
-                } // Store V0 to VX (inclusive) in memory starting at address I
-                0x65 => {
-                    for j in 0..=x {
-                        self.v[j] = self.memory[self.i as usize + j];
-                    }
-                    self.pc += 2;
-                } // Fill V0 to VX (inclusive) with values from memory starting at address I
-                0x75 => {
-                    for j in 0..=x {
-                        self.rpl_user_flags[j] = self.v[j];
-                    }
-                    self.pc += 2;
-                } // Store V0 to VX (inclusive) in RPL user flags
-                0x85 => {
-                    for j in 0..=x {
-                        self.v[j] = self.rpl_user_flags[j];
-                    }
-                    self.pc += 2;
-                } // Read V0 to VX (inclusive) from RPL user flags
-                _ => println!("Unknown opcode: {:#04x}", opcode),
-            },
-            _ => println!("Unknown opcode: {:#04x}", opcode),
-        }
+    let mut rom = None;
+    let mut debug = false;
+    let mut headless = false;
+    let mut limit = Limit::Frames(600);
+    let mut instructions_per_frame = DEFAULT_INSTRUCTIONS_PER_FRAME;
+    let mut dump = None;
+    let mut wav = None;
+    let mut quirks = Quirks::default();
+    let mut buzzer = Buzzer::default();
+    let mut palette = Palette::default();
+    let mut error_policy = ErrorPolicy::default();
+    let mut trace = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut compare = None;
+    let mut load_state = None;
+    let mut save_state = None;
+    let mut rewind = 10;
+    let mut keymap = None;
+    let mut random = RandomAlgorithm::default();
+    let mut seed = None;
+    let mut record = None;
+    let mut replay = None;
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        };
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("Make sound");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--headless" => headless = true,
+            "--cycles" => limit = Limit::Cycles(parse_number(args.next(), arg)?),
+            "--frames" => limit = Limit::Frames(parse_number(args.next(), arg)?),
+            "--ipf" => instructions_per_frame = parse_number(args.next(), arg)?,
+            "--dump" => dump = Some(PathBuf::from(args.next().ok_or("--dump needs a file")?)),
+            "--wav" => wav = Some(PathBuf::from(args.next().ok_or("--wav needs a file")?)),
+            "--tone" => buzzer.frequency = parse_number(args.next(), arg)?,
+            "--waveform" => {
+                buzzer.waveform = args.next().ok_or("--waveform needs a name")?.parse()?
+            }
+            "--volume" => buzzer.volume = parse_number(args.next(), arg)?,
+            "--mute" => buzzer.muted = true,
+            "--keymap" => keymap = Some(PathBuf::from(args.next().ok_or("--keymap needs a file")?)),
+            "--rewind" => rewind = parse_number(args.next(), arg)?,
+            "--palette" => palette = args.next().ok_or("--palette needs colors")?.parse()?,
+            "--on-error" => {
+                let action: ErrorAction =
+                    args.next().ok_or("--on-error needs an action")?.parse()?;
+                error_policy = ErrorPolicy::all(action);
             }
-            self.sound_timer -= 1;
+            "--compare" => {
+                compare = Some(PathBuf::from(args.next().ok_or("--compare needs a file")?))
+            }
+            "--rng" => random = args.next().ok_or("--rng needs a name")?.parse()?,
+            "--seed" => seed = Some(parse_number(args.next(), arg)?),
+            "--record" => record = Some(PathBuf::from(args.next().ok_or("--record needs a file")?)),
+            "--replay" => replay = Some(PathBuf::from(args.next().ok_or("--replay needs a file")?)),
+            "--load-state" => {
+                load_state = Some(PathBuf::from(
+                    args.next().ok_or("--load-state needs a file")?,
+                ))
+            }
+            "--save-state" => {
+                save_state = Some(PathBuf::from(
+                    args.next().ok_or("--save-state needs a file")?,
+                ))
+            }
+            "--trace" => trace = Some(PathBuf::from(args.next().ok_or("--trace needs a file")?)),
+            "--trace-range" => trace_filter.range = Some(parse_range(args.next(), arg)?),
+            "--trace-ops" => {
+                let classes = args.next().ok_or("--trace-ops needs classes")?;
+                trace_filter.classes = classes
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+            }
+            "--quirks" => quirks = args.next().ok_or("--quirks needs a profile")?.parse()?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => rom = Some(arg.clone()),
         }
     }
+
+    Ok(Command::Run(Box::new(Options {
+        rom: rom.ok_or("missing ROM file")?,
+        debug,
+        headless,
+        limit,
+        instructions_per_frame,
+        dump,
+        wav,
+        quirks,
+        buzzer,
+        palette,
+        error_policy,
+        trace,
+        trace_filter,
+        compare,
+        load_state,
+        save_state,
+        rewind,
+        keymap,
+        random,
+        seed,
+        record,
+        replay,
+    })))
 }
 
-impl event::EventHandler for CPU {
-    fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
-        self.emulate_cycle(ctx);
-        self.set_keys(ctx);
-        Ok(())
+fn run(mut options: Options) -> Result<(), Box<dyn Error>> {
+    let mut cpu = CPU::with_quirks(options.quirks);
+    cpu.set_random_algorithm(options.random);
+    if let Some(seed) = options.seed {
+        cpu.set_seed(seed);
+    }
+    if options.record.is_some()
+        && (options.headless
+            || options.debug
+            || options.compare.is_some()
+            || !cfg!(feature = "gui"))
+    {
+        return Err("--record only works when running in a window".into());
+    }
+    let mut replay = None;
+    if let Some(path) = &options.replay {
+        let file = File::open(path).map_err(|source| EmulatorError::File {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let movie = Movie::read(BufReader::new(file))
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        cpu.set_random_algorithm(movie.random);
+        cpu.set_seed(movie.seed);
+        options.instructions_per_frame = movie.instructions_per_frame;
+        options.limit = Limit::Frames(movie.frames.len() as u64);
+        replay = Some((path, movie));
+    }
+    cpu.set_error_policy(options.error_policy);
+    cpu.load_game(&options.rom)?;
+    if let Some(path) = &options.load_state {
+        load_state(&mut cpu, path)?;
+    }
+    let mut input = Vec::new();
+    if let Some((path, movie)) = replay {
+        movie
+            .check_start(&cpu)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        input = movie.frames;
+    }
+    if let Some(path) = &options.trace {
+        let file = BufWriter::new(File::create(path)?);
+        let log = TraceLog::new(file, options.trace_filter.clone());
+        cpu.set_trace_sink(Some(Box::new(log)));
     }
 
-    fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
-        if self.draw_flag {
-            graphics::clear(ctx, [0.1, 0.2, 0.3, 1.0].into());
-            let size = graphics::drawable_size(ctx);
-            let pixel_width = size.0 / 128.0;
-            let pixel_height = size.1 / 64.0;
-            let mut mesh = graphics::MeshBuilder::new();
-
-            for (idx, &pixel) in self.graphics.iter().enumerate() {
-                if pixel != 0 {
-                    let r = graphics::Rect::new(
-                        (idx as f32 % 128.0) * pixel_width,
-                        (idx / 128) as f32 * pixel_height,
-                        pixel_width,
-                        pixel_height,
-                    );
-                    mesh.rectangle(graphics::DrawMode::fill(), r, [0.9, 0.9, 0.9, 1.0].into());
-                }
+    if let Some(path) = &options.compare {
+        let reference = File::open(path).map_err(|source| EmulatorError::File {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let result = chip8::compare_trace(
+            &mut cpu,
+            BufReader::new(reference),
+            options.instructions_per_frame,
+        )?;
+        return match result {
+            Ok(count) => {
+                println!("matched the reference for {} instructions", count);
+                Ok(())
             }
+            Err(divergence) => {
+                print!("{}", divergence);
+                Err("the emulator diverged from the reference".into())
+            }
+        };
+    }
+
+    if options.debug {
+        debug::run(&mut cpu, options.instructions_per_frame)?;
+        return Ok(());
+    }
 
-            self.draw_flag = false;
-            let mesh = mesh.build(ctx)?;
-            graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
-            graphics::present(ctx)?;
+    if options.headless || !cfg!(feature = "gui") {
+        let mut buzzer = options.buzzer;
+        let mut recording = SampleBuffer::default();
+        let audio = options
+            .wav
+            .as_ref()
+            .map(|_| (&mut buzzer, &mut recording as &mut dyn AudioSink));
+        let result = headless::run(
+            &mut cpu,
+            options.limit,
+            options.instructions_per_frame,
+            &input,
+            audio,
+        );
+        if let Some(path) = &options.wav {
+            let mut file = BufWriter::new(File::create(path)?);
+            chip8::write_wav(&mut file, &recording.samples, buzzer.sample_rate())?;
+        }
+        match options.dump {
+            Some(path) => headless::dump(&cpu, &options.palette, &path)?,
+            None => headless::write_text(&cpu, &mut io::stdout())?,
         }
-        std::thread::sleep(std::time::Duration::from_micros(300));
-        Ok(())
+        if let Some(path) = &options.save_state {
+            fs::write(path, cpu.save_state())?;
+        }
+        result?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "gui")]
+    {
+        let keymap = load_keymap(options.keymap.as_deref(), &options.rom)?;
+        gui::run(cpu, options, input, keymap)?;
     }
+    Ok(())
 }
 
-fn main() -> ggez::GameResult {
-    let wm = ggez::conf::WindowMode {
-        width: 640.0,
-        height: 320.0,
-        maximized: false,
-        fullscreen_type: ggez::conf::FullscreenType::Windowed,
-        borderless: false,
-        min_width: 0.0,
-        max_width: 0.0,
-        min_height: 0.0,
-        max_height: 0.0,
-        resizable: true,
+// The keymap for `rom` from `path` or the user's config directory, or the
+// default layout if there is no keymap file.
+#[cfg(feature = "gui")]
+fn load_keymap(path: Option<&Path>, rom: &str) -> Result<chip8::Keymap, Box<dyn Error>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let config = env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+            match config.map(|config| config.join("chip8-emulator").join("keymap")) {
+                Some(path) if path.exists() => path,
+                _ => return Ok(chip8::Keymap::default()),
+            }
+        }
     };
+    let text = fs::read_to_string(&path).map_err(|source| EmulatorError::File {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let name = Path::new(rom)
+        .file_name()
+        .map_or(rom.into(), |name| name.to_string_lossy());
+    let keymap = chip8::Keymap::parse(&text, &name, gui::is_known_key)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(keymap)
+}
+
+// Restore the CPU from a save state file.
+fn load_state(cpu: &mut CPU, path: &Path) -> Result<(), Box<dyn Error>> {
+    let state = fs::read(path).map_err(|source| EmulatorError::File {
+        path: path.display().to_string(),
+        source,
+    })?;
+    cpu.load_state(&state)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(())
+}
 
-    let cb = ggez::ContextBuilder::new("chip8", "haussbrandt").window_mode(wm);
-    let (ctx, event_loop) = &mut cb.build()?;
+// Print the ROM as Octo source, or as a listing of every instruction
+// assuming there is no data between them.
+fn disasm(rom: &str, linear: bool) -> Result<(), Box<dyn Error>> {
+    let game = fs::read(rom).map_err(|source| EmulatorError::File {
+        path: rom.to_string(),
+        source,
+    })?;
+    let mut out = io::stdout().lock();
+    if linear {
+        chip8::write_listing(&mut out, &game)?;
+    } else {
+        chip8::write_disassembly(&mut out, &game)?;
+    }
+    Ok(())
+}
+
+// Assemble Octo source into a ROM file.
+fn asm(path: &Path, rom: &Path) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(path).map_err(|source| EmulatorError::File {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let program = chip8::assemble(&source).map_err(|err| format!("{}: {}", path.display(), err))?;
+    fs::write(rom, program)?;
+    Ok(())
+}
 
-    let state = &mut CPU::new();
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    state.load_game(filename);
-    event::run(ctx, event_loop, state)
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = match command {
+        Command::Run(options) => run(*options),
+        Command::Disasm { rom, linear } => disasm(&rom, linear),
+        Command::Asm { source, rom } => asm(&source, &rom),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,237 @@
+use crate::cpu::CPU;
+use crate::error::MovieError;
+use crate::quirks::Quirks;
+use crate::random::RandomAlgorithm;
+use std::io::{self, BufRead, Write};
+
+const MOVIE_VERSION: u32 = 2;
+
+// The input of a run: the random number generator and its seed, the
+// instruction rate and the state of the 16 keys during every frame, along with
+// the quirks and a checksum of the state the run started from. Replaying it on
+// a CPU that passes check_start reproduces the run exactly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub random: RandomAlgorithm,
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+    pub quirks: Quirks,
+    pub start: u64, // Checksum of the save state before the first frame
+    pub frames: Vec<[bool; 16]>,
+}
+
+impl Movie {
+    // An empty movie of a run starting from the current state of `cpu`.
+    pub fn new(cpu: &CPU, instructions_per_frame: u32) -> Movie {
+        Movie {
+            random: cpu.random_algorithm(),
+            seed: cpu.seed(),
+            instructions_per_frame,
+            quirks: cpu.quirks(),
+            start: checksum(&cpu.save_state()),
+            frames: Vec::new(),
+        }
+    }
+
+    // Check that `cpu`, set up with the generator and seed of the movie, is
+    // where the recorded run started: the quirks match and it holds the same
+    // ROM and loaded save state.
+    pub fn check_start(&self, cpu: &CPU) -> Result<(), MovieError> {
+        if cpu.quirks() != self.quirks {
+            return Err(MovieError::Quirks(self.quirks));
+        }
+        if checksum(&cpu.save_state()) != self.start {
+            return Err(MovieError::Start);
+        }
+        Ok(())
+    }
+
+    // A text file with a header line
+    // `version=2 rng=NAME seed=N ipf=N quirks=PROFILE start=HEX`, where rng
+    // defaults to xorshift, followed by one line per frame holding the keys as
+    // a hex bit mask, bit K set while key K is pressed. Empty lines and lines
+    // starting with `#` are ignored.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# chip8-emulator movie")?;
+        writeln!(
+            out,
+            "version={} rng={} seed={} ipf={} quirks={} start={:016X}",
+            MOVIE_VERSION,
+            self.random,
+            self.seed,
+            self.instructions_per_frame,
+            self.quirks,
+            self.start
+        )?;
+        for keys in &self.frames {
+            let mask = (0..16).fold(0u16, |mask, key| mask | (keys[key] as u16) << key);
+            writeln!(out, "{:04X}", mask)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(input: R) -> Result<Movie, MovieError> {
+        let mut movie = None;
+        for (number, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let syntax = |message: String| MovieError::Syntax {
+                line: number + 1,
+                message,
+            };
+            match &mut movie {
+                None => movie = Some(parse_header(line).map_err(syntax)?),
+                Some(movie) => {
+                    let mask = u16::from_str_radix(line, 16)
+                        .map_err(|_| syntax(format!("invalid key mask: {}", line)))?;
+                    let mut keys = [false; 16];
+                    for (key, pressed) in keys.iter_mut().enumerate() {
+                        *pressed = mask & 1 << key != 0;
+                    }
+                    movie.frames.push(keys);
+                }
+            }
+        }
+        movie.ok_or(MovieError::Syntax {
+            line: 1,
+            message: "missing header".to_string(),
+        })
+    }
+}
+
+fn parse_header(line: &str) -> Result<Movie, String> {
+    let mut version = None;
+    let mut seed = None;
+    let mut instructions_per_frame = None;
+    let mut quirks = None;
+    let mut start = None;
+    let mut movie = Movie::default();
+    for pair in line.split_whitespace() {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or(format!("expected KEY=VALUE, found {}", pair))?;
+        let invalid = || format!("invalid value for {}: {}", key, value);
+        match key {
+            "version" => version = Some(value.parse::<u32>().map_err(|_| invalid())?),
+            "rng" => movie.random = value.parse()?,
+            "seed" => seed = Some(value.parse().map_err(|_| invalid())?),
+            "ipf" => instructions_per_frame = Some(value.parse().map_err(|_| invalid())?),
+            "quirks" => quirks = Some(value.parse()?),
+            "start" => start = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+            _ => return Err(format!("unknown field {}", key)),
+        }
+    }
+    match version {
+        Some(MOVIE_VERSION) => {}
+        Some(version) => return Err(format!("unsupported movie version {}", version)),
+        None => return Err("missing version".to_string()),
+    }
+    movie.seed = seed.ok_or("missing seed")?;
+    movie.instructions_per_frame = instructions_per_frame.ok_or("missing ipf")?;
+    movie.quirks = quirks.ok_or("missing quirks")?;
+    movie.start = start.ok_or("missing start")?;
+    Ok(movie)
+}
+
+// 64-bit FNV-1a.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version=2 rng=lcg seed=7 ipf=15 quirks=xochip start=00000000000000FF";
+
+    fn read(text: &str) -> Result<Movie, MovieError> {
+        Movie::read(text.as_bytes())
+    }
+
+    fn syntax_error(text: &str) -> (usize, String) {
+        match read(text) {
+            Err(MovieError::Syntax { line, message }) => (line, message),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let mut cpu = CPU::with_quirks(Quirks::COSMAC_VIP);
+        cpu.set_random_algorithm(RandomAlgorithm::Vip);
+        cpu.set_seed(1234);
+        let mut movie = Movie::new(&cpu, 11);
+        movie.frames = vec![[false; 16], [true; 16]];
+        movie.frames[0][0xA] = true;
+        let mut text = Vec::new();
+        movie.write(&mut text).unwrap();
+        let read = Movie::read(&text[..]).unwrap();
+        assert_eq!(read, movie);
+        assert_eq!(read.check_start(&cpu).ok(), Some(()));
+    }
+
+    #[test]
+    fn reads_headers_and_key_masks() {
+        let movie = read(&format!("# comment\n\n{}\n0401\nffff\n", HEADER)).unwrap();
+        assert_eq!(movie.random, RandomAlgorithm::Lcg);
+        assert_eq!(movie.seed, 7);
+        assert_eq!(movie.instructions_per_frame, 15);
+        assert_eq!(movie.quirks, Quirks::XO_CHIP);
+        assert_eq!(movie.start, 0xFF);
+        let mut keys = [false; 16];
+        keys[0] = true;
+        keys[0xA] = true;
+        assert_eq!(movie.frames, [keys, [true; 16]]);
+
+        let movie = read("version=2 seed=1 ipf=1 quirks=default start=0").unwrap();
+        assert_eq!(movie.random, RandomAlgorithm::Xorshift);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for (field, message) in [
+            ("version=2 ", "missing version"),
+            ("seed=7 ", "missing seed"),
+            ("ipf=15 ", "missing ipf"),
+            ("quirks=xochip ", "missing quirks"),
+            ("start=00000000000000FF", "missing start"),
+        ] {
+            assert_eq!(syntax_error(&HEADER.replace(field, "")).1, message);
+        }
+        assert_eq!(
+            syntax_error(&HEADER.replace("version=2", "version=1")).1,
+            "unsupported movie version 1"
+        );
+        assert_eq!(
+            syntax_error(&HEADER.replace("ipf=15", "ipf=x")).1,
+            "invalid value for ipf: x"
+        );
+        assert_eq!(
+            syntax_error(&format!("{} frames=3", HEADER)).1,
+            "unknown field frames"
+        );
+        assert_eq!(syntax_error(&format!("{} seed", HEADER)).0, 1);
+        assert_eq!(syntax_error("").1, "missing header");
+        assert_eq!(
+            syntax_error(&format!("{}\n0001\n10000", HEADER)),
+            (3, "invalid key mask: 10000".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_the_start() {
+        let mut cpu = CPU::new();
+        let movie = Movie::new(&cpu, 10);
+        assert!(matches!(
+            movie.check_start(&CPU::with_quirks(Quirks::XO_CHIP)),
+            Err(MovieError::Quirks(quirks)) if quirks == Quirks::default()
+        ));
+        cpu.load_rom(&[0x00, 0xE0]).unwrap();
+        assert!(matches!(movie.check_start(&cpu), Err(MovieError::Start)));
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::str::FromStr;
+
+// How FX55/FX65 move the index register after a register store or load.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexIncrement {
+    #[default]
+    Unchanged, // I is left as it was (SCHIP 1.1)
+    ByX,        // I += X (CHIP-48, SCHIP 1.0)
+    ByXPlusOne, // I += X + 1 (COSMAC VIP, XO-CHIP)
+}
+
+// Behavior switches for the opcodes that differ between CHIP-8 interpreters.
+// The default is the behavior this interpreter has always had: SCHIP 1.1
+// with BNNN using V0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool, // 8XY6/8XYE shift VY into VX instead of VX in place
+    pub load_store_index: IndexIncrement, // FX55/FX65 effect on I
+    pub jump_uses_vx: bool,  // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vf_reset: bool,      // 8XY1/8XY2/8XY3 set VF to 0
+    pub wrap_sprites: bool,  // Sprites wrap around the screen edges instead of clipping
+    pub display_wait: bool,  // DXYN waits for the next vertical blank
+    pub lores_scroll_full: bool, // 00CN/00DN/00FB/00FC scroll whole lores pixels instead of half
+    pub lores_big_sprite: bool, // DXY0 draws a 16x16 sprite in lores mode too
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        vf_reset: true,
+        wrap_sprites: false,
+        display_wait: true,
+        lores_scroll_full: false,
+        lores_big_sprite: false,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        vf_reset: false,
+        wrap_sprites: false,
+        display_wait: false,
+        lores_scroll_full: false,
+        lores_big_sprite: false,
+    };
+
+    pub const SCHIP_1_0: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        vf_reset: false,
+        wrap_sprites: false,
+        display_wait: false,
+        lores_scroll_full: false,
+        lores_big_sprite: false,
+    };
+
+    pub const SCHIP_1_1: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        vf_reset: false,
+        wrap_sprites: false,
+        display_wait: false,
+        lores_scroll_full: false,
+        lores_big_sprite: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        vf_reset: false,
+        wrap_sprites: true,
+        display_wait: false,
+        lores_scroll_full: true,
+        lores_big_sprite: true,
+    };
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    // A profile name, or the names of the switches to turn on joined by `+`
+    // as written by Display for combinations that are not a profile.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => return Ok(Quirks::default()),
+            "vip" | "cosmac" | "chip8" => return Ok(Quirks::COSMAC_VIP),
+            "chip48" => return Ok(Quirks::CHIP_48),
+            "schip10" | "schip1.0" => return Ok(Quirks::SCHIP_1_0),
+            "schip11" | "schip1.1" | "schip" => return Ok(Quirks::SCHIP_1_1),
+            "xochip" | "xo-chip" => return Ok(Quirks::XO_CHIP),
+            _ => {}
+        }
+        let mut quirks = Quirks::default();
+        for flag in name.split('+') {
+            match flag.to_ascii_lowercase().as_str() {
+                "shift-vy" => quirks.shift_uses_vy = true,
+                "index-x" => quirks.load_store_index = IndexIncrement::ByX,
+                "index-x1" => quirks.load_store_index = IndexIncrement::ByXPlusOne,
+                "jump-vx" => quirks.jump_uses_vx = true,
+                "vf-reset" => quirks.vf_reset = true,
+                "wrap" => quirks.wrap_sprites = true,
+                "display-wait" => quirks.display_wait = true,
+                "lores-scroll" => quirks.lores_scroll_full = true,
+                "lores-dxy0" => quirks.lores_big_sprite = true,
+                _ => return Err(format!("unknown quirks profile: {}", name)),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    // The profile name if there is one, otherwise the switches that are on.
+    // SCHIP 1.0 has the same quirks as CHIP-48 and is shown as chip48.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let profiles = [
+            ("default", Quirks::default()),
+            ("vip", Quirks::COSMAC_VIP),
+            ("chip48", Quirks::CHIP_48),
+            ("schip10", Quirks::SCHIP_1_0),
+            ("schip11", Quirks::SCHIP_1_1),
+            ("xochip", Quirks::XO_CHIP),
+        ];
+        if let Some((name, _)) = profiles.iter().find(|(_, quirks)| quirks == self) {
+            return write!(f, "{}", name);
+        }
+        let switches = [
+            (self.shift_uses_vy, "shift-vy"),
+            (self.load_store_index == IndexIncrement::ByX, "index-x"),
+            (
+                self.load_store_index == IndexIncrement::ByXPlusOne,
+                "index-x1",
+            ),
+            (self.jump_uses_vx, "jump-vx"),
+            (self.vf_reset, "vf-reset"),
+            (self.wrap_sprites, "wrap"),
+            (self.display_wait, "display-wait"),
+            (self.lores_scroll_full, "lores-scroll"),
+            (self.lores_big_sprite, "lores-dxy0"),
+        ];
+        let names: Vec<&str> = switches
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu::CPU;
+
+    // A CPU with `quirks` after running the first `steps` instructions of
+    // `source`.
+    fn run(quirks: Quirks, source: &str, steps: usize) -> CPU {
+        let mut cpu = CPU::with_quirks(quirks);
+        cpu.load_rom(&assemble(source).unwrap()).unwrap();
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    fn with(change: impl Fn(&mut Quirks)) -> Quirks {
+        let mut quirks = Quirks::default();
+        change(&mut quirks);
+        quirks
+    }
+
+    #[test]
+    fn display_parses_back() {
+        let profiles = [
+            Quirks::default(),
+            Quirks::COSMAC_VIP,
+            Quirks::CHIP_48,
+            Quirks::SCHIP_1_0,
+            Quirks::SCHIP_1_1,
+            Quirks::XO_CHIP,
+        ];
+        for quirks in profiles {
+            assert_eq!(quirks.to_string().parse(), Ok(quirks));
+        }
+        let custom = with(|quirks| {
+            quirks.vf_reset = true;
+            quirks.load_store_index = IndexIncrement::ByX;
+        });
+        assert_eq!(custom.to_string(), "index-x+vf-reset");
+        assert_eq!(custom.to_string().parse(), Ok(custom));
+        assert_eq!(Quirks::SCHIP_1_0.to_string(), "chip48");
+        assert!("vip+wrap".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        let source = "v1 := 0x81 v0 := 3 v0 >>= v1";
+        let cpu = run(Quirks::default(), source, 3);
+        assert_eq!([cpu.v()[0], cpu.v()[0xF]], [0x01, 1]);
+        let cpu = run(with(|quirks| quirks.shift_uses_vy = true), source, 3);
+        assert_eq!([cpu.v()[0], cpu.v()[0xF]], [0x40, 1]);
+    }
+
+    #[test]
+    fn load_store_index() {
+        for (increment, i) in [
+            (IndexIncrement::Unchanged, 0x300),
+            (IndexIncrement::ByX, 0x302),
+            (IndexIncrement::ByXPlusOne, 0x303),
+        ] {
+            let quirks = with(|quirks| quirks.load_store_index = increment);
+            assert_eq!(run(quirks, "i := 0x300 save v2", 2).i(), i);
+            assert_eq!(run(quirks, "i := 0x300 load v2", 2).i(), i);
+        }
+    }
+
+    #[test]
+    fn jump_uses_vx() {
+        let source = "v0 := 2 v3 := 4 jump0 0x300";
+        assert_eq!(run(Quirks::default(), source, 3).pc(), 0x302);
+        let quirks = with(|quirks| quirks.jump_uses_vx = true);
+        assert_eq!(run(quirks, source, 3).pc(), 0x304);
+    }
+
+    #[test]
+    fn vf_reset() {
+        for operator in ["|=", "&=", "^="] {
+            let source = format!("vf := 5 v0 {} v1", operator);
+            assert_eq!(run(Quirks::default(), &source, 2).v()[0xF], 5);
+            let quirks = with(|quirks| quirks.vf_reset = true);
+            assert_eq!(run(quirks, &source, 2).v()[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn wrap_sprites() {
+        // The top row of the 0 glyph is four pixels wide, two of them past
+        // the right edge. Each lores pixel is 2x2 physical pixels.
+        let source = "v0 := 62 v1 := 0 i := hex v1 sprite v0 v1 1";
+        let cpu = run(Quirks::default(), source, 4);
+        assert_eq!(cpu.graphics().pixel(2 * 63, 0), 1);
+        assert_eq!(cpu.graphics().pixel(0, 0), 0);
+        let cpu = run(with(|quirks| quirks.wrap_sprites = true), source, 4);
+        assert_eq!(cpu.graphics().pixel(2 * 63, 0), 1);
+        assert_eq!(cpu.graphics().pixel(2, 0), 1);
+    }
+
+    #[test]
+    fn display_wait() {
+        let source = "sprite v0 v0 1 v1 := 1";
+        assert_eq!(run(Quirks::default(), source, 2).v()[1], 1);
+        let mut cpu = run(with(|quirks| quirks.display_wait = true), source, 2);
+        assert!(cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.v()[1], 0);
+        cpu.vblank();
+        cpu.step().unwrap();
+        assert_eq!(cpu.v()[1], 1);
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The generator behind CXNN. All of them are deterministic for a seed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RandomAlgorithm {
+    #[default]
+    Xorshift, // xorshift64*, uniform and fast
+    Lcg, // The linear congruential rand() of many C libraries and emulators
+    Vip, // The routine of the COSMAC VIP interpreter, which programs of its time expect
+}
+
+impl RandomAlgorithm {
+    fn id(self) -> u8 {
+        match self {
+            RandomAlgorithm::Xorshift => 0,
+            RandomAlgorithm::Lcg => 1,
+            RandomAlgorithm::Vip => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<RandomAlgorithm> {
+        match id {
+            0 => Some(RandomAlgorithm::Xorshift),
+            1 => Some(RandomAlgorithm::Lcg),
+            2 => Some(RandomAlgorithm::Vip),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for RandomAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "xorshift" => Ok(RandomAlgorithm::Xorshift),
+            "lcg" => Ok(RandomAlgorithm::Lcg),
+            "vip" | "cosmac" => Ok(RandomAlgorithm::Vip),
+            _ => Err(format!("unknown random number generator: {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for RandomAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RandomAlgorithm::Xorshift => write!(f, "xorshift"),
+            RandomAlgorithm::Lcg => write!(f, "lcg"),
+            RandomAlgorithm::Vip => write!(f, "vip"),
+        }
+    }
+}
+
+// Page 0x100 of the COSMAC VIP CHIP-8 interpreter, the CXNN routine at 0x1D9
+// included. The routine reads it as a table of random bytes.
+const VIP_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B,
+];
+
+// A random number generator whose whole state fits in a u64, so that it can
+// be part of a save state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Random {
+    algorithm: RandomAlgorithm,
+    state: u64,
+}
+
+impl Random {
+    pub(crate) fn new(algorithm: RandomAlgorithm, seed: u64) -> Random {
+        let state = match algorithm {
+            // Any seed but 0 works, spread small seeds over all bits
+            RandomAlgorithm::Xorshift => splitmix64(seed).max(1),
+            RandomAlgorithm::Lcg => seed & 0xFFFF_FFFF,
+            RandomAlgorithm::Vip => seed & 0xFFFF,
+        };
+        Random { algorithm, state }
+    }
+
+    pub(crate) fn algorithm(&self) -> RandomAlgorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        match self.algorithm {
+            RandomAlgorithm::Xorshift => {
+                let mut x = self.state;
+                x ^= x >> 12;
+                x ^= x << 25;
+                x ^= x >> 27;
+                self.state = x;
+                (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            RandomAlgorithm::Lcg => {
+                self.state =
+                    (self.state.wrapping_mul(1_103_515_245).wrapping_add(12345)) & 0xFFFF_FFFF;
+                (self.state >> 16) as u8
+            }
+            // The 1802 code of the VIP: increment R9, add the page byte at
+            // R9.0 to R9.1, rotate the sum right through the carry and add
+            // the sum again. The result is the new R9.1.
+            RandomAlgorithm::Vip => {
+                let seed = (self.state as u16).wrapping_add(1);
+                let [low, high] = seed.to_le_bytes();
+                let (sum, carry) = high.overflowing_add(VIP_PAGE[low as usize]);
+                let rotated = sum >> 1 | (carry as u8) << 7;
+                let high = sum.wrapping_add(rotated);
+                self.state = u16::from_le_bytes([low, high]) as u64;
+                high
+            }
+        }
+    }
+
+    // Called at every vertical blank. The VIP interrupt routine increments
+    // R9, so its numbers also depend on the time between CXNN instructions.
+    pub(crate) fn tick(&mut self) {
+        if self.algorithm == RandomAlgorithm::Vip {
+            self.state = (self.state as u16).wrapping_add(1) as u64;
+        }
+    }
+
+    pub(crate) fn to_state(self) -> (u8, u64) {
+        (self.algorithm.id(), self.state)
+    }
+
+    // None for an unknown generator or a state it can never be in, such as
+    // xorshift at 0, where it would only return 0.
+    pub(crate) fn from_state(id: u8, state: u64) -> Option<Random> {
+        let algorithm = RandomAlgorithm::from_id(id)?;
+        let valid = match algorithm {
+            RandomAlgorithm::Xorshift => state != 0,
+            RandomAlgorithm::Lcg => state <= 0xFFFF_FFFF,
+            RandomAlgorithm::Vip => state <= 0xFFFF,
+        };
+        valid.then_some(Random { algorithm, state })
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(algorithm: RandomAlgorithm, seed: u64) -> Vec<u8> {
+        let mut random = Random::new(algorithm, seed);
+        (0..8).map(|_| random.next_byte()).collect()
+    }
+
+    #[test]
+    fn seeds_give_fixed_sequences() {
+        for algorithm in [
+            RandomAlgorithm::Xorshift,
+            RandomAlgorithm::Lcg,
+            RandomAlgorithm::Vip,
+        ] {
+            for seed in [0, 1, 0xFFFF, u64::MAX] {
+                assert_eq!(bytes(algorithm, seed), bytes(algorithm, seed));
+            }
+            assert_ne!(bytes(algorithm, 1), bytes(algorithm, 2));
+        }
+        assert_eq!(
+            bytes(RandomAlgorithm::Xorshift, 0),
+            [0x7B, 0xDE, 0xB3, 0xE0, 0x7F, 0x6E, 0x41, 0x0C]
+        );
+        assert_eq!(
+            bytes(RandomAlgorithm::Lcg, 0),
+            [0x00, 0xDC, 0x04, 0x65, 0xAA, 0x1F, 0xAD, 0x1D]
+        );
+    }
+
+    // Worked through the VIP code by hand: R9.1 stays 0 while R9.0 indexes
+    // the zeros at the start of the page, 0x105 holds 0x45, 0x45 rotated
+    // right is 0x22 and 0x45 + 0x22 = 0x67. Then 0x67 + 0xA3 = 0x10A with a
+    // carry, rotated 0x85, 0x0A + 0x85 = 0x8F.
+    #[test]
+    fn vip_follows_the_interpreter() {
+        assert_eq!(
+            bytes(RandomAlgorithm::Vip, 0)[..6],
+            [0x00, 0x00, 0x00, 0x00, 0x67, 0x8F]
+        );
+        let mut random = Random::new(RandomAlgorithm::Vip, 3);
+        random.tick();
+        assert_eq!(random.next_byte(), 0x67);
+    }
+
+    #[test]
+    fn rejects_impossible_states() {
+        assert_eq!(Random::from_state(0, 0), None);
+        assert!(Random::from_state(0, 1).is_some());
+        assert_eq!(Random::from_state(1, 1 << 32), None);
+        assert_eq!(Random::from_state(2, 0x1_0000), None);
+        assert_eq!(Random::from_state(3, 1), None);
+        let random = Random::new(RandomAlgorithm::Vip, 0x1234);
+        let (id, state) = random.to_state();
+        assert_eq!(Random::from_state(id, state), Some(random));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in [
+            RandomAlgorithm::Xorshift,
+            RandomAlgorithm::Lcg,
+            RandomAlgorithm::Vip,
+        ] {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::cpu::CPU;
+use std::collections::VecDeque;
+
+// The history of the last `capacity` frames as a save state every `interval`
+// frames plus the keys of every frame after it. A frame in between is
+// restored by loading the state before it and running the frames up to it
+// again, which is deterministic as the state includes the random number
+// generator. Whole snapshots are dropped once the history is too long.
+pub struct Rewind {
+    snapshots: VecDeque<(Vec<u8>, Vec<[bool; 16]>)>,
+    capacity: usize,
+    interval: usize,
+    frames: usize, // Frames recorded in all snapshots
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: u32) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::new(),
+            capacity,
+            interval: interval.max(1) as usize,
+            frames: 0,
+        }
+    }
+
+    // Call before running each frame on `cpu` with `keys` passed to
+    // CPU::set_keys.
+    pub fn record(&mut self, cpu: &CPU, keys: [bool; 16]) {
+        if self.capacity == 0 {
+            return;
+        }
+        match self.snapshots.back_mut() {
+            Some((_, frames)) if frames.len() < self.interval => frames.push(keys),
+            _ => self.snapshots.push_back((cpu.save_state(), vec![keys])),
+        }
+        self.frames += 1;
+        while let Some((_, frames)) = self.snapshots.front() {
+            if self.frames - frames.len() < self.capacity {
+                break;
+            }
+            self.frames -= frames.len();
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Restore `cpu` to the state before the last recorded frame and forget
+    // that frame, so that repeated calls step further back. Returns false if
+    // there is none left.
+    pub fn rewind(&mut self, cpu: &mut CPU, instructions_per_frame: u32) -> bool {
+        let (state, frames) = match self.snapshots.back_mut() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        frames.pop();
+        self.frames -= 1;
+        // Written by save_state, so it cannot be invalid
+        if cpu.load_state(state).is_err() {
+            return false;
+        }
+        for &keys in frames.iter() {
+            cpu.set_keys(keys);
+            // Errors were reported when the frame first ran
+            let _ = cpu.run_frame(instructions_per_frame);
+        }
+        if frames.is_empty() {
+            self.snapshots.pop_back();
+        }
+        true
+    }
+
+    // The number of frames that can be rewound.
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    const INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+    fn cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.set_seed(1);
+        let rom = assemble(
+            "loop
+                v0 += 5
+                v1 := random 0xFF
+                v2 := 3
+                if v2 key then v3 += 1
+             again",
+        )
+        .unwrap();
+        cpu.load_rom(&rom).unwrap();
+        cpu
+    }
+
+    fn keys(frame: usize) -> [bool; 16] {
+        let mut keys = [false; 16];
+        keys[3] = frame.is_multiple_of(3);
+        keys
+    }
+
+    // Run `frames` frames with `rewind` recording, returning the state before
+    // each frame.
+    fn run(cpu: &mut CPU, rewind: &mut Rewind, frames: usize) -> Vec<Vec<u8>> {
+        let mut states = Vec::new();
+        for frame in 0..frames {
+            states.push(cpu.save_state());
+            rewind.record(cpu, keys(frame));
+            cpu.set_keys(keys(frame));
+            cpu.run_frame(INSTRUCTIONS_PER_FRAME).unwrap();
+        }
+        states
+    }
+
+    #[test]
+    fn restores_every_earlier_state() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(100, 4);
+        let states = run(&mut cpu, &mut rewind, 30);
+        for state in states.iter().rev() {
+            assert!(rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+            assert!(cpu.save_state() == *state);
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+    }
+
+    #[test]
+    fn forgets_frames_beyond_the_capacity() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(10, 4);
+        let states = run(&mut cpu, &mut rewind, 30);
+        assert!((10..10 + 4).contains(&rewind.len()));
+        let kept = rewind.len();
+        for state in states.iter().rev().take(kept) {
+            assert!(rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+            assert!(cpu.save_state() == *state);
+        }
+        assert!(!rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+
+        let mut rewind = Rewind::new(0, 4);
+        run(&mut cpu, &mut rewind, 5);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn starts_over_after_clear() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(100, 4);
+        let loaded = run(&mut cpu, &mut rewind, 7)[2].clone();
+        cpu.load_state(&loaded).unwrap();
+        rewind.clear();
+        assert!(!rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+        let states = run(&mut cpu, &mut rewind, 3);
+        assert!(states[0] == loaded);
+        for state in states.iter().rev() {
+            assert!(rewind.rewind(&mut cpu, INSTRUCTIONS_PER_FRAME));
+            assert!(cpu.save_state() == *state);
+        }
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::error::StateError;
+
+// Save states start with this magic and a little endian format version, which
+// must be incremented whenever the layout written by CPU::save_state changes.
+const MAGIC: &[u8; 4] = b"C8ST";
+pub(crate) const STATE_VERSION: u16 = 3;
+
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> Self {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.bytes(MAGIC);
+        writer.u16(STATE_VERSION);
+        writer
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader { data };
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(StateError::NotAState);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    // Fails if there is data left over.
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("trailing data"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(7);
+        writer.bool(true);
+        writer.u16(0x1234);
+        writer.u64(u64::MAX - 1);
+        writer.bytes(b"abc");
+        writer.finish()
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let data = state();
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.u8(), Ok(7));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        assert_eq!(reader.array::<3>(), Ok(*b"abc"));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(matches!(StateReader::new(b""), Err(StateError::NotAState)));
+        assert!(matches!(
+            StateReader::new(b"C8S"),
+            Err(StateError::NotAState)
+        ));
+        assert!(matches!(
+            StateReader::new(b"PNG\x00\x03\x00"),
+            Err(StateError::NotAState)
+        ));
+        assert!(matches!(
+            StateReader::new(b"C8ST\x02\x00"),
+            Err(StateError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = state();
+        assert!(matches!(
+            StateReader::new(&data[..5]),
+            Err(StateError::Truncated)
+        ));
+        let mut reader = StateReader::new(&data[..data.len() - 1]).unwrap();
+        reader.bytes(12).unwrap();
+        assert_eq!(reader.array::<3>(), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut data = state();
+        data[7] = 2;
+        let mut reader = StateReader::new(&data).unwrap();
+        reader.u8().unwrap();
+        assert_eq!(reader.bool(), Err(StateError::Invalid("boolean")));
+
+        let mut data = state();
+        data.push(0);
+        let mut reader = StateReader::new(&data).unwrap();
+        reader.bytes(data.len() - 7).unwrap();
+        assert_eq!(reader.finish(), Err(StateError::Invalid("trailing data")));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::cpu::CPU;
+use crate::instruction::{decode, Instruction, InstructionClass};
+use std::io::{self, Write};
+use std::ops::Range;
+
+// Receives every instruction the CPU executes, before it is executed.
+pub trait TraceSink {
+    fn trace(&mut self, cpu: &CPU, opcode: u16);
+}
+
+// Which instructions a trace includes. An empty class list includes all.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub range: Option<Range<u16>>,
+    pub classes: Vec<InstructionClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instruction: Instruction) -> bool {
+        let in_range = self.range.as_ref().is_none_or(|range| range.contains(&pc));
+        let in_class = self.classes.is_empty() || self.classes.contains(&instruction.class());
+        in_range && in_class
+    }
+}
+
+// Write the state of `cpu` before executing `opcode` as one trace line:
+//
+//   cycle=N pc=XXXX op=XXXX v0=XX ... vF=XX i=XXXX sp=N dt=XX st=XX ; MNEMONIC
+//
+// The cycle is the decimal number of instructions executed before, all other
+// values are hex except the stack depth and everything after `;` is comment.
+pub fn write_trace_line<W: Write>(
+    out: &mut W,
+    cycle: u64,
+    cpu: &CPU,
+    opcode: u16,
+) -> io::Result<()> {
+    write!(out, "cycle={} pc={:04X} op={:04X}", cycle, cpu.pc(), opcode)?;
+    for (x, value) in cpu.v().iter().enumerate() {
+        write!(out, " v{:X}={:02X}", x, value)?;
+    }
+    writeln!(
+        out,
+        " i={:04X} sp={} dt={:02X} st={:02X} ; {}",
+        cpu.i(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        decode(opcode)
+    )
+}
+
+// Writes the instructions passing `filter` as trace lines. Counts every
+// executed instruction, so cycle numbers stay comparable across filters.
+pub struct TraceLog<W: Write> {
+    out: W,
+    filter: TraceFilter,
+    cycle: u64,
+    failed: bool,
+}
+
+impl<W: Write> TraceLog<W> {
+    pub fn new(out: W, filter: TraceFilter) -> Self {
+        TraceLog {
+            out,
+            filter,
+            cycle: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TraceLog<W> {
+    fn trace(&mut self, cpu: &CPU, opcode: u16) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.failed || !self.filter.matches(cpu.pc(), decode(opcode)) {
+            return;
+        }
+        // Tracing must not stop the program, so report the first error only
+        if let Err(err) = write_trace_line(&mut self.out, cycle, cpu, opcode) {
+            eprintln!("cannot write trace: {}", err);
+            self.failed = true;
+        }
+    }
+}
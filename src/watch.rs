@@ -0,0 +1,340 @@
+use crate::cpu::CPU;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+// The kind of memory access a watchpoint pauses on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "read/write"),
+        }
+    }
+}
+
+// Pauses when an instruction reads or writes data in `range`. Instruction
+// fetches do not count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: Range<usize>,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    // The first watched address the last step of `cpu` accessed, and how.
+    pub fn hit(&self, cpu: &CPU) -> Option<(usize, Access)> {
+        let overlap = |accessed: Option<Range<usize>>| {
+            let accessed = accessed?;
+            let start = accessed.start.max(self.range.start);
+            if start < accessed.end.min(self.range.end) {
+                Some(start)
+            } else {
+                None
+            }
+        };
+        if self.access != Access::Read {
+            if let Some(address) = overlap(cpu.memory_written()) {
+                return Some((address, Access::Write));
+            }
+        }
+        if self.access != Access::Write {
+            if let Some(address) = overlap(cpu.memory_read()) {
+                return Some((address, Access::Read));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04X}..{:04X}",
+            self.access, self.range.start, self.range.end
+        )
+    }
+}
+
+// A value in a condition: a register, a timer or a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),
+    I,
+    PC,
+    SP,
+    Delay,
+    Sound,
+    Number(u16),
+}
+
+impl Operand {
+    fn value(self, cpu: &CPU) -> u16 {
+        match self {
+            Operand::V(x) => cpu.v()[x as usize] as u16,
+            Operand::I => cpu.i(),
+            Operand::PC => cpu.pc(),
+            Operand::SP => cpu.sp(),
+            Operand::Delay => cpu.delay_timer() as u16,
+            Operand::Sound => cpu.sound_timer() as u16,
+            Operand::Number(value) => value,
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    // Numbers are decimal or hex with a 0x prefix.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let lower = text.to_ascii_lowercase();
+        let operand = match lower.as_str() {
+            "i" => Operand::I,
+            "pc" => Operand::PC,
+            "sp" => Operand::SP,
+            "delay" | "dt" => Operand::Delay,
+            "sound" | "st" => Operand::Sound,
+            _ => {
+                let number = if let Some(register) = lower.strip_prefix('v') {
+                    u8::from_str_radix(register, 16)
+                        .ok()
+                        .filter(|&x| x < 16)
+                        .map(Operand::V)
+                } else if let Some(hex) = lower.strip_prefix("0x") {
+                    u16::from_str_radix(hex, 16).ok().map(Operand::Number)
+                } else {
+                    lower.parse().ok().map(Operand::Number)
+                };
+                number.ok_or(format!("unknown value: {}", text))?
+            }
+        };
+        Ok(operand)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::V(x) => write!(f, "v{:X}", x),
+            Operand::I => write!(f, "i"),
+            Operand::PC => write!(f, "pc"),
+            Operand::SP => write!(f, "sp"),
+            Operand::Delay => write!(f, "delay"),
+            Operand::Sound => write!(f, "sound"),
+            Operand::Number(value) => write!(f, "{:#X}", value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    const SYMBOLS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<", Comparison::Less),
+        ("<=", Comparison::LessEqual),
+        (">", Comparison::Greater),
+        (">=", Comparison::GreaterEqual),
+    ];
+}
+
+// A condition on the CPU state, e.g. `v3 == 0x10` or `i outside 0x200..0x1000`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Compare(Operand, Comparison, Operand),
+    Inside(Operand, u16, u16),  // Start inclusive, end exclusive
+    Outside(Operand, u16, u16), // Start inclusive, end exclusive
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        match *self {
+            Condition::Compare(left, comparison, right) => {
+                let (left, right) = (left.value(cpu), right.value(cpu));
+                match comparison {
+                    Comparison::Equal => left == right,
+                    Comparison::NotEqual => left != right,
+                    Comparison::Less => left < right,
+                    Comparison::LessEqual => left <= right,
+                    Comparison::Greater => left > right,
+                    Comparison::GreaterEqual => left >= right,
+                }
+            }
+            Condition::Inside(operand, start, end) => (start..end).contains(&operand.value(cpu)),
+            Condition::Outside(operand, start, end) => !(start..end).contains(&operand.value(cpu)),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (left, operator, right) = match words.as_slice() {
+            [left, operator, right] => (left.parse()?, *operator, *right),
+            _ => return Err(format!("expected VALUE OPERATOR VALUE: {}", text)),
+        };
+        if operator == "inside" || operator == "outside" {
+            let (start, end) = right
+                .split_once("..")
+                .ok_or(format!("expected a range START..END: {}", right))?;
+            let bound = |bound: &str| match bound.parse() {
+                Ok(Operand::Number(value)) => Ok(value),
+                _ => Err(format!("invalid range bound: {}", bound)),
+            };
+            let (start, end) = (bound(start)?, bound(end)?);
+            return Ok(if operator == "inside" {
+                Condition::Inside(left, start, end)
+            } else {
+                Condition::Outside(left, start, end)
+            });
+        }
+        let comparison = Comparison::SYMBOLS
+            .iter()
+            .find(|(symbol, _)| *symbol == operator)
+            .map(|&(_, comparison)| comparison)
+            .ok_or(format!("unknown operator: {}", operator))?;
+        Ok(Condition::Compare(left, comparison, right.parse()?))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Compare(left, comparison, right) => {
+                let symbol = Comparison::SYMBOLS
+                    .iter()
+                    .find(|(_, other)| other == comparison)
+                    .map_or("?", |(symbol, _)| symbol);
+                write!(f, "{} {} {}", left, symbol, right)
+            }
+            Condition::Inside(operand, start, end) => {
+                write!(f, "{} inside {:#X}..{:#X}", operand, start, end)
+            }
+            Condition::Outside(operand, start, end) => {
+                write!(f, "{} outside {:#X}..{:#X}", operand, start, end)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // A CPU that has run `steps` instructions of a ROM that saves v0 and v1
+    // to 0x300 and loads v0 back from 0x301.
+    fn run(steps: usize) -> CPU {
+        let mut cpu = CPU::new();
+        let rom = assemble(
+            "i := 0x300
+             v0 := 7
+             save v1
+             i := 0x301
+             load v0",
+        )
+        .unwrap();
+        cpu.load_rom(&rom).unwrap();
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    fn watch(range: Range<usize>, access: Access) -> Watchpoint {
+        Watchpoint { range, access }
+    }
+
+    #[test]
+    fn hits_accesses_in_range() {
+        let (save, load) = (run(3), run(5));
+        assert_eq!(
+            watch(0x301..0x310, Access::Write).hit(&save),
+            Some((0x301, Access::Write))
+        );
+        assert_eq!(watch(0x301..0x310, Access::Write).hit(&load), None);
+        assert_eq!(
+            watch(0x301..0x302, Access::ReadWrite).hit(&load),
+            Some((0x301, Access::Read))
+        );
+        assert_eq!(watch(0x300..0x310, Access::Read).hit(&save), None);
+        assert_eq!(watch(0x302..0x310, Access::ReadWrite).hit(&save), None);
+        assert_eq!(watch(0x300..0x301, Access::ReadWrite).hit(&load), None);
+        assert_eq!(watch(0x300..0x310, Access::ReadWrite).hit(&run(4)), None);
+    }
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!(
+            "v3 == 0x10".parse(),
+            Ok(Condition::Compare(
+                Operand::V(3),
+                Comparison::Equal,
+                Operand::Number(0x10)
+            ))
+        );
+        assert_eq!(
+            "PC >= dt".parse(),
+            Ok(Condition::Compare(
+                Operand::PC,
+                Comparison::GreaterEqual,
+                Operand::Delay
+            ))
+        );
+        assert_eq!(
+            "i outside 0x200..4096".parse(),
+            Ok(Condition::Outside(Operand::I, 0x200, 0x1000))
+        );
+        for text in ["vF != 255", "sp < 1", "st inside 0..0x10"] {
+            let condition: Condition = text.parse().unwrap();
+            assert_eq!(condition.to_string().parse(), Ok(condition));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_conditions() {
+        let parse = |text: &str| text.parse::<Condition>().unwrap_err();
+        assert_eq!(parse("v3 =="), "expected VALUE OPERATOR VALUE: v3 ==");
+        assert_eq!(parse("v3 ~ 1"), "unknown operator: ~");
+        assert_eq!(parse("vg == 1"), "unknown value: vg");
+        assert_eq!(parse("v10 == 1"), "unknown value: v10");
+        assert_eq!(
+            parse("i inside 0x200"),
+            "expected a range START..END: 0x200"
+        );
+        assert_eq!(parse("i inside 0..pc"), "invalid range bound: pc");
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let cpu = run(2);
+        let holds = |text: &str| text.parse::<Condition>().unwrap().holds(&cpu);
+        assert!(holds("v0 == 7"));
+        assert!(!holds("v0 != 7"));
+        assert!(holds("v0 < 8") && holds("v0 <= 7") && !holds("v0 > 7"));
+        assert!(holds("i >= 0x300") && holds("pc == 0x204"));
+        assert!(holds("i inside 0x300..0x301"));
+        assert!(!holds("i inside 0x301..0x400"));
+        assert!(holds("i outside 0x200..0x300"));
+    }
+}
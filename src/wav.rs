@@ -0,0 +1,26 @@
+use std::io::{self, Write};
+
+// Write mono f32 samples as a 16-bit PCM WAV file.
+pub fn write_wav<W: Write>(out: &mut W, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // Chunk size
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // Mono
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?; // Byte rate
+    out.write_all(&2u16.to_le_bytes())?; // Block align
+    out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}